@@ -0,0 +1,262 @@
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, ExternalPrinter, Helper};
+use sd_client::{Client, DropletId, DropletInfo, Error, ServerType};
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const USAGE :&str = "Usage: sd-cli [host:port]";
+
+const PROMPT :&str = "sd> ";
+
+// Name, arguments and description of every command the server runs, plus the
+// client's own `help`.
+const HELP :&[(&str, &str, &str)] = &[
+    ("register", "<email> <password>", "Create an account and log in"),
+    ("login", "<email> <password>", "Log in to an existing account"),
+    ("ls", "[-m]", "Show the stock and price of each server type, or with -m your and your orgs' droplets"),
+    ("prices", "[Fast|Slow]", "Show this week's scheduled price changes"),
+    ("history", "<Fast|Slow> [30m|24h|7d]", "Show recent auction results and clearing prices"),
+    ("buy", "[-o org] <Fast|Slow> [lease]", "Buy a droplet at the listed price, for a lease like 24h or until dropped"),
+    ("profile", "", "Show your email and charges so far"),
+    ("quota", "[-o org]", "Show your or an org's purchase quotas and what is left of them"),
+    ("drop", "<id>", "Terminate one of your droplets"),
+    ("start", "<id>", "Start a stopped droplet"),
+    ("stop", "<id>", "Stop a running droplet"),
+    ("reboot", "<id>", "Reboot a running droplet"),
+    ("renew", "<id> <length>", "Extend a droplet's lease, e.g. by 24h"),
+    ("auction", "[-o org] <Fast|Slow> <amount> [lease]", "Bid for a droplet; queues the bid when out of stock"),
+    ("orgs", "", "List your organizations and invites"),
+    ("org", "<name>", "Show an organization's members, droplets and charges"),
+    ("org-create", "<name>", "Create an organization you own"),
+    ("org-invite", "<name> <email> <role>", "Invite a member as owner, purchaser or viewer"),
+    ("org-join", "<name>", "Accept an invite"),
+    ("org-leave", "<name>", "Leave an organization or decline its invite"),
+    ("org-remove", "<name> <email>", "Remove a member or invite"),
+    ("org-role", "<name> <email> <role>", "Change a member's role"),
+    ("proto", "<framed|plain>", "Reply format; managed by this client"),
+    ("quit", "", "Close the connection and exit"),
+    ("help", "[command]", "Show this help, or the help for one command"),
+    ("stock-add", "<Fast|Slow> [amount]", "(admin) Add stock"),
+    ("stock-rm", "<Fast|Slow> [amount]", "(admin) Remove stock"),
+    ("clients", "", "(admin) List accounts"),
+    ("market", "", "(admin) Count stock, held droplets, auctions and queued bids"),
+    ("suspend", "<email>", "(admin) Suspend an account"),
+    ("unsuspend", "<email>", "(admin) Lift a suspension"),
+    ("delete", "<email>", "(admin) Delete an account and release its droplets"),
+    ("force-drop", "<id>", "(admin) Terminate any droplet"),
+    ("cancel-auction", "<Fast|Slow>", "(admin) Cancel a running auction"),
+    ("lockouts", "", "(admin) List accounts and addresses with failed logins"),
+    ("unlock", "<email|address>", "(admin) Clear a login lockout"),
+];
+
+const TYPE_ARG :&[&str] = &["buy", "prices", "history", "auction", "stock-add", "stock-rm", "cancel-auction"];
+
+const ID_ARG :&[&str] = &["drop", "start", "stop", "reboot", "renew", "force-drop"];
+
+// Completes command names, server types and the ids of droplets seen in `ls -m`.
+struct CliHelper {
+    ids :Arc<Mutex<Vec<DropletId>>>,
+}
+
+impl Completer for CliHelper {
+    type Candidate = String;
+
+    fn complete(&self, line :&str, pos :usize, _ :&Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let words = line[..start].split_whitespace().collect::<Vec<_>>();
+        let candidates = match words[..] {
+            [] => HELP.iter().map(|(c, _, _)| c.to_string()).collect(),
+            [cmd] if TYPE_ARG.contains(&cmd) =>
+                ServerType::ALL.iter().map(|st| st.name().to_owned()).collect(),
+            [cmd] if ID_ARG.contains(&cmd) =>
+                self.ids.lock().unwrap().iter().map(|id| id.to_string()).collect(),
+            ["ls"] => vec!["-m".to_owned()],
+            ["help"] => HELP.iter().map(|(c, _, _)| c.to_string()).collect(),
+            _ => Vec::new(),
+        };
+        Ok((start, candidates.into_iter().filter(|c :&String| c.starts_with(word)).collect()))
+    }
+}
+
+impl Hinter for CliHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CliHelper {}
+
+impl Validator for CliHelper {}
+
+impl Helper for CliHelper {}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let addr = match args.first().map(String::as_str) {
+        Some("-h") | Some("--help") => { eprintln!("{}", USAGE); process::exit(2) },
+        Some(addr) => addr.to_owned(),
+        None => "127.0.0.1:12345".to_owned(),
+    };
+    let mut client = match Client::connect(&addr) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("sd-cli: cannot connect to {}: {}", addr, e);
+            process::exit(1);
+        },
+    };
+    let ids = Arc::new(Mutex::new(Vec::new()));
+    let mut rl = match Editor::<CliHelper, DefaultHistory>::new() {
+        Ok(rl) => rl,
+        Err(e) => {
+            eprintln!("sd-cli: {}", e);
+            process::exit(1);
+        },
+    };
+    rl.set_helper(Some(CliHelper { ids: Arc::clone(&ids) }));
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".sd_history"));
+    if let Some(history) = history.as_ref() {
+        let _ = rl.load_history(history);
+    }
+
+    // Notifications arrive while the user is typing; the external printer
+    // redraws the prompt and the line being edited underneath them.
+    let events = client.take_events().unwrap();
+    let mut printer = rl.create_external_printer().ok();
+    thread::spawn(move || {
+        for event in events {
+            let msg = format!("* {}", event);
+            match printer.as_mut() {
+                Some(printer) => { let _ = printer.print(msg); },
+                None => println!("{}", msg),
+            }
+        }
+    });
+
+    loop {
+        let line = match rl.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => { eprintln!("sd-cli: {}", e); break },
+        };
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        if words.is_empty() { continue }
+        // Keep passwords out of the history file.
+        if words[0] != "register" && words[0] != "login" {
+            let _ = rl.add_history_entry(line.as_str());
+        }
+        match run(&mut client, &ids, &words) {
+            Ok(Some(out)) => println!("{}", out),
+            Ok(None) => break,
+            Err(e) => println!("error: {}", e),
+        }
+    }
+    if let Some(history) = history.as_ref() {
+        let _ = rl.save_history(history);
+    }
+}
+
+// Runs one command line; `None` means the user asked to leave.
+fn run(client :&mut Client, ids :&Mutex<Vec<DropletId>>, words :&[&str]) -> Result<Option<String>, Error> {
+    Ok(Some(match words {
+        ["quit"] | ["exit"] => return Ok(None),
+        ["help"] => help(None),
+        ["help", cmd] => help(Some(cmd)),
+        ["proto", ..] => "Reply format is managed by sd-cli".to_owned(),
+        ["ls"] => {
+            let mut stock = client.stock()?;
+            stock.sort_by_key(|o| o.server_type);
+            table(&["Type", "In stock", "Price/h"], stock.iter()
+                  .map(|o| vec![o.server_type.to_string(), o.available.to_string(), o.price.to_string()])
+                  .collect())
+        },
+        ["ls", "-m"] => {
+            let droplets = client.my_droplets()?;
+            *ids.lock().unwrap() = droplets.iter().map(|d| d.id.clone()).collect();
+            table(&["ID", "Type", "State", "Uptime", "Expires", "Owner"], droplets.iter().map(droplet_row).collect())
+        },
+        ["buy", st] if ServerType::parse(st).is_some() => {
+            let id = client.buy(ServerType::parse(st).unwrap())?;
+            ids.lock().unwrap().push(id.clone());
+            format!("Bought droplet {}", id)
+        },
+        ["drop", id] if DropletId::parse(id).is_some() => {
+            let id = DropletId::parse(id).unwrap();
+            client.drop(&id)?;
+            ids.lock().unwrap().retain(|i| *i != id);
+            format!("Dropped {}", id)
+        },
+        _ => reply(&client.command(&words.join(" "))?),
+    }))
+}
+
+fn help(command :Option<&str>) -> String {
+    let line = |(c, args, desc) :&(&str, &str, &str)| format!("  {:<45} {}", format!("{} {}", c, args), desc);
+    match command {
+        None => HELP.iter().map(line).collect::<Vec<_>>().join("\n"),
+        Some(cmd) => HELP.iter()
+            .find(|(c, _, _)| *c == cmd)
+            .map(line)
+            .unwrap_or_else(|| format!("No such command: {}", cmd)),
+    }
+}
+
+fn droplet_row(d :&DropletInfo) -> Vec<String> {
+    let uptime = d.uptime.map(|u| {
+        let s = u.as_secs();
+        format!("{}h{:02}m{:02}s", s / 3600, s / 60 % 60, s % 60)
+    });
+    vec![d.id.to_string(), d.server_type.to_string(), d.state.name().to_owned(), uptime.unwrap_or_else(|| "-".into()),
+         d.expires.clone().unwrap_or_else(|| "-".into()), d.owner.clone()]
+}
+
+// Redraws the server's tab separated tables (header, `===` line, rows) with aligned
+// columns, keeping any summary lines above them.
+fn reply(body :&str) -> String {
+    let lines = body.lines().collect::<Vec<_>>();
+    match lines.iter().position(|l| l.starts_with("===")) {
+        Some(rule) if rule > 0 => {
+            let rows = lines[rule + 1..].iter().map(|l| l.split('\t').map(str::to_owned).collect()).collect();
+            lines[..rule - 1].iter()
+                .map(|l| l.to_string())
+                .chain(Some(table(&lines[rule - 1].split('\t').collect::<Vec<_>>(), rows)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        },
+        _ => body.to_owned(),
+    }
+}
+
+fn table(header :&[&str], rows :Vec<Vec<String>>) -> String {
+    let mut widths = header.iter().map(|h| h.len()).collect::<Vec<_>>();
+    for row in rows.iter() {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let line = |cells :Vec<&str>| cells.iter()
+        .zip(widths.iter())
+        .map(|(c, w)| format!("{:<w$}", c, w = w))
+        .collect::<Vec<_>>()
+        .join("  ")
+        .trim_end()
+        .to_owned();
+    let mut out = vec![
+        line(header.to_vec()),
+        line(widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>().iter().map(String::as_str).collect()),
+    ];
+    if rows.is_empty() {
+        out.push("(none)".to_owned());
+    }
+    out.extend(rows.iter().map(|r| line(r.iter().map(String::as_str).collect())));
+    out.join("\n")
+}
@@ -0,0 +1,357 @@
+use sd_client::{Client, DropletId, Error, Event, ServerType};
+
+use std::collections::HashMap;
+use std::env;
+use std::process;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const USAGE :&str = "Usage: sd-load [-a <host:port>] [-c <connections>] [-d <seconds>] [-r <per second>] \
+                     [-m <command=weight,...>] [--admin <email:password>]
+
+    -a        server address (default 127.0.0.1:12345)
+    -c        concurrent connections, one synthetic account each (default 16)
+    -d        how long to run, in seconds (default 10)
+    -r        commands per second on each connection (default as fast as replies come)
+    -m        command mix (default buy=4,drop=4,ls=2,auction=2,profile=1)
+    --admin   admin account used to check stock + reserved = seeded total
+
+Every account is rate limited, by default to 2 trades and 10 reads a second, so
+an unpaced run mostly measures the limiter. Raise the limits in the server's
+config, e.g. `rate.trade = 1000 1000` and `rate.read = 1000 1000`, or pace the
+connections below them with -r. A run where most commands were throttled fails.";
+
+const COMMANDS :&[&str] = &["buy", "drop", "ls", "auction", "profile"];
+
+#[derive(Debug)]
+struct Options {
+    addr :String,
+    connections :usize,
+    duration :Duration,
+    interval :Option<Duration>,
+    mix :Vec<(&'static str, u32)>,
+    admin :Option<(String, String)>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options {
+        addr: "127.0.0.1:12345".into(),
+        connections: 16,
+        duration: Duration::from_secs(10),
+        interval: None,
+        mix: vec![("buy", 4), ("drop", 4), ("ls", 2), ("auction", 2), ("profile", 1)],
+        admin: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" { return Err(String::new()) }
+        let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+        match flag.as_str() {
+            "-a" => opts.addr = value,
+            "-c" => opts.connections = value.parse().ok().filter(|&c| c > 0)
+                .ok_or_else(|| format!("Invalid connection count: {}", value))?,
+            "-d" => opts.duration = value.parse().map(Duration::from_secs_f64)
+                .map_err(|_| format!("Invalid duration: {}", value))?,
+            "-r" => opts.interval = value.parse::<f64>().ok().filter(|r| *r > 0.0 && r.is_finite())
+                .map(|r| Some(Duration::from_secs_f64(1.0 / r)))
+                .ok_or_else(|| format!("Invalid rate: {}", value))?,
+            "-m" => opts.mix = parse_mix(&value)?,
+            "--admin" => {
+                let mut parts = value.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(email), Some(password)) => opts.admin = Some((email.into(), password.into())),
+                    _ => return Err(format!("Invalid admin account: {}", value)),
+                }
+            },
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    Ok(opts)
+}
+
+fn parse_mix(s :&str) -> Result<Vec<(&'static str, u32)>, String> {
+    let mix = s.split(',')
+        .map(|entry| {
+            let mut parts = entry.splitn(2, '=');
+            let name = parts.next().unwrap_or("");
+            let command = COMMANDS.iter().find(|c| **c == name)
+                .ok_or_else(|| format!("Unknown command in mix: {}", name))?;
+            let weight = parts.next().unwrap_or("1").parse()
+                .map_err(|_| format!("Invalid weight in mix: {}", entry))?;
+            Ok((*command, weight))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if mix.iter().all(|(_, w)| *w == 0) {
+        return Err("The command mix needs a non zero weight".into())
+    }
+    Ok(mix)
+}
+
+// Xorshift; the load only needs to be varied, not unpredictable.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed :u64) -> Self {
+        Rng(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n :u64) -> u64 {
+        self.next() % n
+    }
+
+    fn server_type(&mut self) -> ServerType {
+        ServerType::ALL[self.below(ServerType::ALL.len() as u64) as usize]
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    latencies :Vec<Duration>,
+    ok :u64,
+    errors :HashMap<&'static str, u64>,
+}
+
+impl Stats {
+    fn merge(&mut self, other :Stats) {
+        self.latencies.extend(other.latencies);
+        self.ok += other.ok;
+        for (kind, n) in other.errors {
+            *self.errors.entry(kind).or_insert(0) += n;
+        }
+    }
+
+    fn percentile(&self, p :f64) -> Duration {
+        let i = ((self.latencies.len() - 1) as f64 * p).round() as usize;
+        self.latencies[i]
+    }
+}
+
+fn error_kind(e :&Error) -> &'static str {
+    match e {
+        Error::Io(_) => "io",
+        Error::Disconnected => "disconnected",
+        Error::Protocol(_) => "unexpected reply",
+        Error::LoginRequired => "login required",
+        Error::OutOfStock(_) => "out of stock",
+        Error::EmailTaken(_) => "email taken",
+        Error::InvalidClient(_) => "invalid client",
+        Error::PermissionDenied(_) => "permission denied",
+        Error::Suspended(_) => "suspended",
+        Error::InvalidDroplet(_) => "invalid droplet",
+        Error::InvalidState(_) => "invalid state",
+        Error::NoAuction(_) => "no auction",
+        Error::NoLease(_) => "no lease",
+        Error::QuotaExceeded(_) => "quota exceeded",
+        Error::NoOrg(_) => "no organization",
+        Error::OrgTaken(_) => "organization taken",
+        Error::NotMember(_, _) => "not a member",
+        Error::AlreadyMember(_, _) => "already a member",
+        Error::NoInvite(_) => "no invite",
+        Error::LastOwner(_) => "last owner",
+        Error::BidTooLow(_) => "bid too low",
+        Error::InvalidBid(_) => "invalid bid",
+        Error::TooManyAttempts(_) => "login lockout",
+        Error::Throttled(_) => "throttled",
+        Error::ShuttingDown => "shutting down",
+        Error::Busy => "server busy",
+        Error::Internal => "internal error",
+        Error::Server(_) => "other",
+    }
+}
+
+fn worker(opts :Arc<Options>, n :usize, run :u64, start :Arc<Barrier>)
+    -> Result<HashMap<&'static str, Stats>, Error> {
+
+    let mut client = Client::connect(&opts.addr[..])?;
+    let events = client.take_events().unwrap();
+    client.register(&format!("load-{}-{}@sd-load", run, n), "load")?;
+    let total :u32 = opts.mix.iter().map(|(_, w)| w).sum();
+    let mut rng = Rng::new(run ^ ((n as u64 + 1) << 32));
+    let mut owned :Vec<DropletId> = Vec::new();
+    let mut stats :HashMap<&'static str, Stats> = HashMap::new();
+
+    start.wait();
+    let deadline = Instant::now() + opts.duration;
+    let mut next = Instant::now();
+    while Instant::now() < deadline {
+        if let Some(interval) = opts.interval {
+            let now = Instant::now();
+            if next > now { thread::sleep(next - now) }
+            next = now.max(next) + interval;
+        }
+        // Auctions won and queued bids granted also hand out droplets.
+        for event in events.try_iter() {
+            match event {
+                Event::Won { id, .. } | Event::Granted { id, .. } => owned.push(id),
+                _ => {},
+            }
+        }
+        let mut pick = rng.below(total as u64) as u32;
+        let command = opts.mix.iter()
+            .find(|(_, w)| if pick < *w { true } else { pick -= w; false })
+            .map(|(c, _)| *c)
+            .unwrap();
+        let began = Instant::now();
+        let result = match command {
+            "buy" => client.buy(rng.server_type()).map(|id| owned.push(id)),
+            "drop" if owned.is_empty() => continue,
+            "drop" => {
+                let id = owned.swap_remove(rng.below(owned.len() as u64) as usize);
+                client.drop(&id)
+            },
+            "ls" => client.stock().map(|_| ()),
+            "auction" => client.bid(rng.server_type(), 1 + rng.below(100) as i32).map(|_| ()),
+            "profile" => client.command("profile").map(|_| ()),
+            _ => unreachable!(),
+        };
+        let stats = stats.entry(command).or_default();
+        stats.latencies.push(began.elapsed());
+        match result {
+            Ok(()) => stats.ok += 1,
+            Err(e) => *stats.errors.entry(error_kind(&e)).or_insert(0) += 1,
+        }
+    }
+    Ok(stats)
+}
+
+// Stock, and every unit reserved: held by a client or an org, or taken by a
+// running auction. Read from the admin `market` counts, which change nothing.
+fn market_total(opts :&Options) -> Result<Option<(u64, u64)>, Error> {
+    let (email, password) = match opts.admin.as_ref() {
+        Some(admin) => admin,
+        None => return Ok(None),
+    };
+    let mut client = Client::connect(&opts.addr[..])?;
+    client.login(email, password)?;
+    let (mut stock, mut reserved) = (0, 0);
+    for line in client.command("market")?.lines().skip_while(|l| !l.starts_with("===")).skip(1) {
+        let counts = line.split('\t')
+            .skip(1)
+            .take(3)
+            .map(|n| n.parse::<u64>().map_err(|_| Error::Protocol(line.to_owned())))
+            .collect::<Result<Vec<_>, Error>>()?;
+        match counts[..] {
+            [s, held, auctions] => { stock += s; reserved += held + auctions },
+            _ => return Err(Error::Protocol(line.to_owned())),
+        }
+    }
+    Ok(Some((stock, reserved)))
+}
+
+fn ms(d :Duration) -> String {
+    format!("{:.2}ms", d.as_secs_f64() * 1000.0)
+}
+
+fn main() {
+    let opts = match parse_args() {
+        Ok(opts) => Arc::new(opts),
+        Err(e) => {
+            if !e.is_empty() { eprintln!("sd-load: {}", e) }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+    };
+    let seeded = match market_total(&opts) {
+        Ok(total) => total.map(|(stock, reserved)| stock + reserved),
+        Err(e) => {
+            eprintln!("sd-load: cannot read the market: {}", e);
+            process::exit(1);
+        },
+    };
+
+    let run = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    let start = Arc::new(Barrier::new(opts.connections + 1));
+    let workers = (0..opts.connections)
+        .map(|n| {
+            let opts = Arc::clone(&opts);
+            let start = Arc::clone(&start);
+            thread::spawn(move || {
+                // A worker that cannot set up still has to release the others.
+                let result = worker(opts, n, run, Arc::clone(&start));
+                if result.is_err() { start.wait(); }
+                result
+            })
+        })
+        .collect::<Vec<_>>();
+    start.wait();
+    let began = Instant::now();
+
+    let mut stats :HashMap<&'static str, Stats> = HashMap::new();
+    let mut failed = 0;
+    for worker in workers {
+        match worker.join().unwrap() {
+            Ok(worker_stats) => for (command, s) in worker_stats {
+                stats.entry(command).or_default().merge(s);
+            },
+            Err(e) => {
+                eprintln!("sd-load: connection failed: {}", e);
+                failed += 1;
+            },
+        }
+    }
+    let elapsed = began.elapsed();
+
+    let total = stats.values().map(|s| s.latencies.len()).sum::<usize>();
+    println!("{} commands in {:.1}s over {} connections: {:.1}/s",
+             total, elapsed.as_secs_f64(), opts.connections - failed, total as f64 / elapsed.as_secs_f64());
+    println!();
+    println!("{:<8} {:>8} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+             "Command", "Count", "OK", "Errors", "p50", "p90", "p99", "max");
+    for command in COMMANDS.iter() {
+        let s = match stats.get_mut(command) {
+            Some(s) if !s.latencies.is_empty() => s,
+            _ => continue,
+        };
+        s.latencies.sort();
+        println!("{:<8} {:>8} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+                 command, s.latencies.len(), s.ok, s.errors.values().sum::<u64>(),
+                 ms(s.percentile(0.5)), ms(s.percentile(0.9)), ms(s.percentile(0.99)),
+                 ms(*s.latencies.last().unwrap()));
+    }
+    let mut errors = stats.iter()
+        .flat_map(|(command, s)| s.errors.iter().map(move |(kind, n)| (*command, *kind, *n)))
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        errors.sort();
+        println!();
+        println!("{:<8} {:<18} {:>8}", "Command", "Error", "Count");
+        for (command, kind, n) in errors {
+            println!("{:<8} {:<18} {:>8}", command, kind, n);
+        }
+    }
+
+    let throttled = stats.values().filter_map(|s| s.errors.get("throttled")).sum::<u64>();
+    let limited = throttled as usize * 2 > total;
+    if limited {
+        println!();
+        println!("{} of {} commands were throttled, this run measured the rate limiter;", throttled, total);
+        println!("raise the server's rate.* limits or pace the connections with -r");
+    }
+
+    println!();
+    match (seeded, market_total(&opts)) {
+        (None, _) => println!("Invariant not checked, pass --admin to check stock + reserved"),
+        (Some(_), Err(e)) => {
+            println!("Invariant not checked: {}", e);
+            process::exit(1);
+        },
+        (Some(seeded), Ok(Some((stock, reserved)))) if stock + reserved == seeded =>
+            println!("Invariant holds: stock {} + reserved {} = seeded {}", stock, reserved, seeded),
+        (Some(seeded), Ok(Some((stock, reserved)))) => {
+            println!("Invariant VIOLATED: stock {} + reserved {} != seeded {}", stock, reserved, seeded);
+            process::exit(1);
+        },
+        (Some(_), Ok(None)) => unreachable!(),
+    }
+    if failed > 0 || limited {
+        process::exit(1);
+    }
+}
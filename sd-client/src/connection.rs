@@ -0,0 +1,96 @@
+use crate::error::Error;
+use crate::event::Event;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub type Reply = Result<String, String>;
+
+// One framed connection. A reader thread splits what arrives into replies and
+// events; when the socket closes the reply channel hangs up.
+#[derive(Debug)]
+pub struct Connection {
+    stream :TcpStream,
+    replies :Receiver<Reply>,
+    closing :Arc<AtomicBool>,
+}
+
+impl Connection {
+    pub fn open<A :ToSocketAddrs>(addr :A, events :Sender<Event>, timeout :Duration) -> Result<Self, Error> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        // The server answers in plain text until asked to frame, and a busy
+        // server turns the connection away with a plain line too.
+        stream.write_all(b"proto framed\n")?;
+        stream.set_read_timeout(Some(timeout))?;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        match line.trim_end() {
+            "ok" => while line.trim_end() != "." {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 { return Err(Error::Disconnected) }
+            },
+            "" => return Err(Error::Disconnected),
+            "error" => return Err(Error::Protocol("server does not support framed replies".into())),
+            refusal => return Err(Error::from_reply(refusal)),
+        }
+        stream.set_read_timeout(None)?;
+        let (tx, replies) = mpsc::channel();
+        let closing = Arc::new(AtomicBool::new(false));
+        let reader_closing = Arc::clone(&closing);
+        thread::spawn(move || read_loop(reader, tx, events, reader_closing));
+        Ok(Connection { stream, replies, closing })
+    }
+
+    pub fn request(&mut self, line :&str, timeout :Duration) -> io::Result<Reply> {
+        self.stream.write_all(format!("{}\n", line).as_bytes())?;
+        match self.replies.recv_timeout(timeout) {
+            Ok(reply) => Ok(reply),
+            Err(RecvTimeoutError::Timeout) => Err(io::ErrorKind::TimedOut.into()),
+            Err(RecvTimeoutError::Disconnected) => Err(io::ErrorKind::ConnectionAborted.into()),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.closing.store(true, Ordering::SeqCst);
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+fn read_loop(reader :BufReader<TcpStream>, replies :Sender<Reply>, events :Sender<Event>, closing :Arc<AtomicBool>) {
+    let mut frame :Option<(bool, Vec<String>)> = None;
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        frame = match frame.take() {
+            None => match line.as_str() {
+                "ok" => Some((true, Vec::new())),
+                "error" => Some((false, Vec::new())),
+                _ => { let _ = events.send(Event::parse(&line)); None },
+            },
+            Some((ok, body)) if line == "." => {
+                let body = body.join("\n");
+                let _ = replies.send(if ok { Ok(body) } else { Err(body) });
+                None
+            },
+            Some((ok, mut body)) => {
+                body.push(line.strip_prefix('.').map(|l| l.to_owned()).unwrap_or(line));
+                Some((ok, body))
+            },
+        };
+    }
+    // Closing the connection ourselves is not worth an event.
+    if !closing.load(Ordering::SeqCst) {
+        let _ = events.send(Event::Disconnected);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::ServerType;
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Disconnected,
+    Protocol(String),
+    LoginRequired,
+    OutOfStock(ServerType),
+    EmailTaken(String),
+    InvalidClient(String),
+    PermissionDenied(String),
+    Suspended(String),
+    InvalidDroplet(String),
+    InvalidState(String),
+    NoAuction(ServerType),
+    NoLease(String),
+    QuotaExceeded(String),
+    NoOrg(String),
+    OrgTaken(String),
+    // The organization, then the email.
+    NotMember(String, String),
+    AlreadyMember(String, String),
+    NoInvite(String),
+    LastOwner(String),
+    BidTooLow(i32),
+    InvalidBid(i32),
+    TooManyAttempts(Duration),
+    Throttled(Duration),
+    ShuttingDown,
+    Busy,
+    Internal,
+    Server(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(e :io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+fn secs(s :&str) -> Option<Duration> {
+    s.trim_end_matches('s').parse::<f64>().ok().map(Duration::from_secs_f64)
+}
+
+impl Error {
+    // Maps the text of an error reply back to what the server meant.
+    pub(crate) fn from_reply(msg :&str) -> Self {
+        let after = |prefix :&str| msg.strip_prefix(prefix).map(|s| s.to_owned());
+        let server_type = |prefix :&str| after(prefix).as_deref().and_then(ServerType::parse);
+        if msg == "You must be logged in to use this!" {
+            Error::LoginRequired
+        } else if msg == "Server is shutting down" {
+            Error::ShuttingDown
+        } else if msg == "Server busy, try again later" {
+            Error::Busy
+        } else if msg == "500: Internal Server Error" {
+            Error::Internal
+        } else if let Some(st) = server_type("Out of stock: ") {
+            Error::OutOfStock(st)
+        } else if let Some(st) = server_type("No auction running for ") {
+            Error::NoAuction(st)
+        } else if let Some(e) = after("Email Taken: ") {
+            Error::EmailTaken(e)
+        } else if let Some(e) = after("Invalid client: ") {
+            Error::InvalidClient(e)
+        } else if let Some(e) = after("Permission denied for ") {
+            Error::PermissionDenied(e)
+        } else if let Some(e) = after("Account suspended: ") {
+            Error::Suspended(e)
+        } else if let Some(id) = after("Invalid Server id").or_else(|| after("Invalid id: ")) {
+            Error::InvalidDroplet(id.trim_start_matches(": ").to_owned())
+        } else if let Some(id) = after("No lease on server ") {
+            Error::NoLease(id)
+        } else if let Some(quota) = after("Quota exceeded: ") {
+            Error::QuotaExceeded(quota)
+        } else if let Some(org) = after("No such organization: ") {
+            Error::NoOrg(org)
+        } else if let Some(org) = after("Organization name taken: ") {
+            Error::OrgTaken(org)
+        } else if let Some((org, email)) = after("Not a member of ").as_deref().and_then(|s| s.split_once(": ")) {
+            Error::NotMember(org.to_owned(), email.to_owned())
+        } else if let Some((org, email)) = after("Already a member of ").as_deref().and_then(|s| s.split_once(": ")) {
+            Error::AlreadyMember(org.to_owned(), email.to_owned())
+        } else if let Some(org) = after("No invite to ") {
+            Error::NoInvite(org)
+        } else if let Some(org) = after("Organization needs another owner first: ") {
+            Error::LastOwner(org)
+        } else if let Some(state) = after("Not allowed while server ") {
+            Error::InvalidState(state.rsplit(' ').next().unwrap_or("").to_owned())
+        } else if let Some(top) = after("Bid too low, top bid is ").and_then(|v| v.parse().ok()) {
+            Error::BidTooLow(top)
+        } else if let Some(amount) = after("Bid must be positive: ").and_then(|v| v.parse().ok()) {
+            Error::InvalidBid(amount)
+        } else if let Some(wait) = after("Too many failed logins, retry in ").as_deref().and_then(secs) {
+            Error::TooManyAttempts(wait)
+        } else if let Some(wait) = after("Too many requests, retry in ").as_deref().and_then(secs) {
+            Error::Throttled(wait)
+        } else {
+            Error::Server(msg.to_owned())
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f :&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Disconnected => write!(f, "Disconnected from server"),
+            Error::Protocol(e) => write!(f, "Unexpected reply: {}", e),
+            Error::LoginRequired => write!(f, "You must be logged in to use this"),
+            Error::OutOfStock(st) => write!(f, "Out of stock: {}", st),
+            Error::EmailTaken(e) => write!(f, "Email taken: {}", e),
+            Error::InvalidClient(e) => write!(f, "Invalid client: {}", e),
+            Error::PermissionDenied(e) => write!(f, "Permission denied for {}", e),
+            Error::Suspended(e) => write!(f, "Account suspended: {}", e),
+            Error::InvalidDroplet(id) => write!(f, "Invalid droplet id: {}", id),
+            Error::InvalidState(state) => write!(f, "Droplet is {}", state),
+            Error::NoAuction(st) => write!(f, "No auction running for {}", st),
+            Error::NoLease(id) => write!(f, "Droplet {} has no lease", id),
+            Error::QuotaExceeded(quota) => write!(f, "Quota exceeded: {}", quota),
+            Error::NoOrg(org) => write!(f, "No such organization: {}", org),
+            Error::OrgTaken(org) => write!(f, "Organization name taken: {}", org),
+            Error::NotMember(org, email) => write!(f, "{} is not a member of {}", email, org),
+            Error::AlreadyMember(org, email) => write!(f, "{} is already a member of {}", email, org),
+            Error::NoInvite(org) => write!(f, "No invite to {}", org),
+            Error::LastOwner(org) => write!(f, "{} needs another owner first", org),
+            Error::BidTooLow(top) => write!(f, "Bid too low, top bid is {}", top),
+            Error::InvalidBid(amount) => write!(f, "Bid must be positive, got {}", amount),
+            Error::TooManyAttempts(wait) => write!(f, "Too many failed logins, retry in {}s", wait.as_secs()),
+            Error::Throttled(wait) => write!(f, "Too many requests, retry in {:.1}s", wait.as_secs_f64()),
+            Error::ShuttingDown => write!(f, "Server is shutting down"),
+            Error::Busy => write!(f, "Server busy, try again later"),
+            Error::Internal => write!(f, "Internal server error"),
+            Error::Server(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,94 @@
+use crate::{DropletId, ServerType};
+
+use std::fmt;
+use std::time::Duration;
+
+pub const NOTICE :&str = "Notice: ";
+
+// Something the server pushed without being asked, or a change in the connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Outbid { server_type :ServerType, top :i32 },
+    Won { server_type :ServerType, id :DropletId },
+    Lost { server_type :ServerType, winning :i32 },
+    Cancelled { server_type :ServerType },
+    Granted { server_type :ServerType, id :DropletId },
+    LeaseEnding { id :DropletId, left :Duration },
+    LeaseEnded { id :DropletId },
+    ShuttingDown,
+    IdleTimeout,
+    Disconnected,
+    Reconnected,
+    Other(String),
+}
+
+fn between<'a>(s :&'a str, prefix :&str, suffix :&str) -> Option<&'a str> {
+    s.strip_prefix(prefix)?.split(suffix).next()
+}
+
+impl Event {
+    pub(crate) fn parse(line :&str) -> Self {
+        let notice = match line.strip_prefix(NOTICE) {
+            Some(notice) => notice,
+            None => return match line {
+                "Server is shutting down" => Event::ShuttingDown,
+                "Idle timeout, closing connection" => Event::IdleTimeout,
+                _ => Event::Other(line.to_owned()),
+            },
+        };
+        let last = notice.rsplit(' ').next().unwrap_or("");
+        let st = |s :Option<&str>| s.and_then(ServerType::parse);
+        let parsed = if notice.starts_with("Outbid on the ") {
+            st(between(notice, "Outbid on the ", " "))
+                .zip(last.parse().ok())
+                .map(|(server_type, top)| Event::Outbid { server_type, top })
+        } else if notice.starts_with("Won the ") {
+            st(between(notice, "Won the ", " "))
+                .zip(DropletId::parse(last))
+                .map(|(server_type, id)| Event::Won { server_type, id })
+        } else if notice.starts_with("Lost the ") {
+            st(between(notice, "Lost the ", " "))
+                .zip(last.parse().ok())
+                .map(|(server_type, winning)| Event::Lost { server_type, winning })
+        } else if notice.ends_with(" auction cancelled by an admin") {
+            st(notice.split(' ').next())
+                .map(|server_type| Event::Cancelled { server_type })
+        } else if notice.starts_with("Queued ") {
+            st(between(notice, "Queued ", " "))
+                .zip(DropletId::parse(last))
+                .map(|(server_type, id)| Event::Granted { server_type, id })
+        } else if notice.starts_with("Lease ending in ") {
+            between(notice, "Lease ending in ", "s")
+                .and_then(|s| s.parse().ok())
+                .zip(DropletId::parse(last))
+                .map(|(secs, id)| Event::LeaseEnding { id, left: Duration::from_secs(secs) })
+        } else if notice.starts_with("Lease ended, ") {
+            DropletId::parse(last).map(|id| Event::LeaseEnded { id })
+        } else {
+            None
+        };
+        parsed.unwrap_or_else(|| Event::Other(notice.to_owned()))
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f :&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Outbid { server_type, top } =>
+                write!(f, "Outbid on the {} auction, top bid is now {}", server_type, top),
+            Event::Won { server_type, id } => write!(f, "Won the {} auction, droplet {}", server_type, id),
+            Event::Lost { server_type, winning } =>
+                write!(f, "Lost the {} auction, winning bid was {}", server_type, winning),
+            Event::Cancelled { server_type } => write!(f, "{} auction cancelled by an admin", server_type),
+            Event::Granted { server_type, id } => write!(f, "Queued {} bid granted, droplet {}", server_type, id),
+            Event::LeaseEnding { id, left } =>
+                write!(f, "Lease ending in {}s, renew to keep droplet {}", left.as_secs(), id),
+            Event::LeaseEnded { id } => write!(f, "Lease ended, released droplet {}", id),
+            Event::ShuttingDown => write!(f, "Server is shutting down"),
+            Event::IdleTimeout => write!(f, "Idle timeout, connection closed"),
+            Event::Disconnected => write!(f, "Connection to the server lost"),
+            Event::Reconnected => write!(f, "Reconnected to the server"),
+            Event::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! Typed client for the sd-rust auction protocol.
+//!
+//! ```no_run
+//! use sd_client::{Client, ServerType};
+//!
+//! let mut client = Client::connect("127.0.0.1:12345")?;
+//! client.login("me@example.com", "secret")?;
+//! let id = client.buy(ServerType::Fast)?;
+//! client.drop(&id)?;
+//! # Ok::<(), sd_client::Error>(())
+//! ```
+
+mod connection;
+mod error;
+mod event;
+
+pub use error::Error;
+pub use event::Event;
+
+use connection::Connection;
+
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+// Commands that change nothing on the server and can be re-sent after a reconnect.
+const IDEMPOTENT :&[&str] = &["ls", "profile", "quota"];
+
+const PURCHASED :&str = "Purchase successfull! Droplet id: ";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ServerType {
+    Slow,
+    Fast,
+}
+
+impl ServerType {
+    pub const ALL :[ServerType; 2] = [ServerType::Slow, ServerType::Fast];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServerType::Slow => "Slow",
+            ServerType::Fast => "Fast",
+        }
+    }
+
+    pub fn parse(s :&str) -> Option<Self> {
+        match s {
+            "Slow" => Some(ServerType::Slow),
+            "Fast" => Some(ServerType::Fast),
+            _ => None,
+        }
+    }
+
+    fn prefix(&self) -> &'static str {
+        match self {
+            ServerType::Slow => "slow-",
+            ServerType::Fast => "fast-",
+        }
+    }
+}
+
+impl fmt::Display for ServerType {
+    fn fmt(&self, f :&mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Droplet ids are opaque to clients; only the type prefix is looked at.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DropletId(String);
+
+impl DropletId {
+    pub fn parse(s :&str) -> Option<Self> {
+        ServerType::ALL.iter()
+            .find(|st| s.len() > st.prefix().len() && s.starts_with(st.prefix()))
+            .map(|_| DropletId(s.to_owned()))
+    }
+
+    pub fn server_type(&self) -> ServerType {
+        *ServerType::ALL.iter().find(|st| self.0.starts_with(st.prefix())).unwrap()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for DropletId {
+    fn fmt(&self, f :&mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DropletState {
+    Provisioning,
+    Running,
+    Stopped,
+    Terminated,
+}
+
+impl DropletState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DropletState::Provisioning => "provisioning",
+            DropletState::Running => "running",
+            DropletState::Stopped => "stopped",
+            DropletState::Terminated => "terminated",
+        }
+    }
+
+    pub fn parse(s :&str) -> Option<Self> {
+        match s {
+            "provisioning" => Some(DropletState::Provisioning),
+            "running" => Some(DropletState::Running),
+            "stopped" => Some(DropletState::Stopped),
+            "terminated" => Some(DropletState::Terminated),
+            _ => None,
+        }
+    }
+}
+
+// What an organization lets a member do, each role including the ones before it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Purchaser,
+    Owner,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Purchaser => "purchaser",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(s :&str) -> Option<Self> {
+        match s {
+            "viewer" => Some(Role::Viewer),
+            "purchaser" => Some(Role::Purchaser),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DropletInfo {
+    pub id :DropletId,
+    pub server_type :ServerType,
+    pub state :DropletState,
+    pub uptime :Option<Duration>,
+    // When the lease ends, in the server's local time, if the droplet has one.
+    pub expires :Option<String>,
+    // The user's email, or the name of one of their organizations.
+    pub owner :String,
+}
+
+// One row of `ls`: what is in stock and what `buy` charges per hour for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Offer {
+    pub server_type :ServerType,
+    pub available :u32,
+    pub price :i32,
+}
+
+// One row of `quota`. Spend is what the held droplets cost over the quota's period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaInfo {
+    pub name :String,
+    pub used :f64,
+    pub limit :f64,
+    pub left :f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BidOutcome {
+    // A new auction was opened with this bid.
+    Started { closes_in :Duration },
+    // The bid leads the running auction.
+    TopBid,
+    // No stock: the bid waits in the queue.
+    Queued { position :usize },
+    // As `Queued`, replacing an earlier queued bid.
+    Requeued { position :usize },
+}
+
+#[derive(Debug)]
+pub struct Client {
+    addrs :Vec<SocketAddr>,
+    conn :Option<Connection>,
+    credentials :Option<(String, String)>,
+    events_tx :Sender<Event>,
+    events :Option<Receiver<Event>>,
+    timeout :Duration,
+    retries :u32,
+}
+
+impl Client {
+    pub fn connect<A :ToSocketAddrs>(addr :A) -> Result<Self, Error> {
+        let addrs = addr.to_socket_addrs()?.collect::<Vec<_>>();
+        let (events_tx, events) = mpsc::channel();
+        let timeout = Duration::from_secs(10);
+        let conn = Connection::open(&addrs[..], events_tx.clone(), timeout)?;
+        Ok(Client {
+            addrs,
+            conn: Some(conn),
+            credentials: None,
+            events_tx,
+            events: Some(events),
+            timeout,
+            retries: 5,
+        })
+    }
+
+    // How long to wait for a reply before giving up on the connection.
+    pub fn set_timeout(&mut self, timeout :Duration) {
+        self.timeout = timeout;
+    }
+
+    // Connection attempts made, with growing delays, once the server is lost.
+    pub fn set_retries(&mut self, retries :u32) {
+        self.retries = retries;
+    }
+
+    // Pushed notifications and connection changes. Can be taken only once.
+    pub fn take_events(&mut self) -> Option<Receiver<Event>> {
+        self.events.take()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    pub fn register(&mut self, email :&str, password :&str) -> Result<(), Error> {
+        self.command(&format!("register {} {}", email, password))?;
+        self.credentials = Some((email.to_owned(), password.to_owned()));
+        Ok(())
+    }
+
+    pub fn login(&mut self, email :&str, password :&str) -> Result<(), Error> {
+        self.command(&format!("login {} {}", email, password))?;
+        self.credentials = Some((email.to_owned(), password.to_owned()));
+        Ok(())
+    }
+
+    pub fn stock(&mut self) -> Result<Vec<Offer>, Error> {
+        let reply = self.command("ls")?;
+        rows(&reply)
+            .map(|row| offer(&row).ok_or_else(|| Error::Protocol(row.join("\t"))))
+            .collect()
+    }
+
+    // Only the quotas the server has configured are listed.
+    pub fn quotas(&mut self) -> Result<Vec<QuotaInfo>, Error> {
+        let reply = self.command("quota")?;
+        rows(&reply)
+            .map(|row| quota(&row).ok_or_else(|| Error::Protocol(row.join("\t"))))
+            .collect()
+    }
+
+    pub fn buy(&mut self, server_type :ServerType) -> Result<DropletId, Error> {
+        self.purchase(&format!("buy {}", server_type))
+    }
+
+    // Buys a droplet that goes back to stock once `lease` is over, unless renewed.
+    pub fn buy_leased(&mut self, server_type :ServerType, lease :Duration) -> Result<DropletId, Error> {
+        self.purchase(&format!("buy {} {}", server_type, length(lease)))
+    }
+
+    // Buys a droplet that belongs to the organization rather than the user.
+    pub fn buy_for(&mut self, org :&str, server_type :ServerType) -> Result<DropletId, Error> {
+        self.purchase(&format!("buy -o {} {}", org, server_type))
+    }
+
+    pub fn renew(&mut self, id :&DropletId, by :Duration) -> Result<(), Error> {
+        self.command(&format!("renew {} {}", id, length(by))).map(|_| ())
+    }
+
+    fn purchase(&mut self, line :&str) -> Result<DropletId, Error> {
+        let reply = self.command(line)?;
+        // The id is followed by the hourly price it was bought at.
+        reply.strip_prefix(PURCHASED)
+            .and_then(|rest| rest.split(' ').next())
+            .and_then(DropletId::parse)
+            .ok_or(Error::Protocol(reply))
+    }
+
+    pub fn drop(&mut self, id :&DropletId) -> Result<(), Error> {
+        self.command(&format!("drop {}", id)).map(|_| ())
+    }
+
+    pub fn bid(&mut self, server_type :ServerType, amount :i32) -> Result<BidOutcome, Error> {
+        self.place(&format!("auction {} {}", server_type, amount))
+    }
+
+    // Bids for the organization; a won droplet belongs to it.
+    pub fn bid_for(&mut self, org :&str, server_type :ServerType, amount :i32) -> Result<BidOutcome, Error> {
+        self.place(&format!("auction -o {} {} {}", org, server_type, amount))
+    }
+
+    fn place(&mut self, line :&str) -> Result<BidOutcome, Error> {
+        let reply = self.command(line)?;
+        let last = reply.rsplit(' ').next().unwrap_or("");
+        let outcome = if reply.contains(" auction started, closing in ") {
+            last.trim_end_matches('s').parse().ok()
+                .map(|s| BidOutcome::Started { closes_in: Duration::from_secs(s) })
+        } else if reply.starts_with("Bid of ") {
+            Some(BidOutcome::TopBid)
+        } else if reply.starts_with("Earlier ") {
+            last.parse().ok().map(|position| BidOutcome::Requeued { position })
+        } else if reply.contains(" out of stock, bid queued ") {
+            last.parse().ok().map(|position| BidOutcome::Queued { position })
+        } else {
+            None
+        };
+        outcome.ok_or(Error::Protocol(reply))
+    }
+
+    pub fn create_org(&mut self, name :&str) -> Result<(), Error> {
+        self.command(&format!("org-create {}", name)).map(|_| ())
+    }
+
+    pub fn invite(&mut self, org :&str, email :&str, role :Role) -> Result<(), Error> {
+        self.command(&format!("org-invite {} {} {}", org, email, role.name())).map(|_| ())
+    }
+
+    pub fn join_org(&mut self, org :&str) -> Result<Role, Error> {
+        let reply = self.command(&format!("org-join {}", org))?;
+        reply.rsplit(' ').next().and_then(Role::parse).ok_or(Error::Protocol(reply))
+    }
+
+    pub fn leave_org(&mut self, org :&str) -> Result<(), Error> {
+        self.command(&format!("org-leave {}", org)).map(|_| ())
+    }
+
+    // Also lists the droplets of the user's organizations.
+    pub fn my_droplets(&mut self) -> Result<Vec<DropletInfo>, Error> {
+        let reply = self.command("ls -m")?;
+        rows(&reply)
+            .map(|row| droplet(&row).ok_or_else(|| Error::Protocol(row.join("\t"))))
+            .collect()
+    }
+
+    // Sends one command line and returns the body of a successful reply.
+    pub fn command(&mut self, line :&str) -> Result<String, Error> {
+        let idempotent = line.split_whitespace().next().is_some_and(|c| IDEMPOTENT.contains(&c));
+        let reply = match self.request(line) {
+            Err(Error::Disconnected) if idempotent => {
+                self.reconnect()?;
+                self.request(line)?
+            },
+            reply => reply?,
+        };
+        reply.map_err(|e| Error::from_reply(&e))
+    }
+
+    fn request(&mut self, line :&str) -> Result<connection::Reply, Error> {
+        if self.conn.is_none() {
+            self.reconnect()?;
+        }
+        let timeout = self.timeout;
+        match self.conn.as_mut().unwrap().request(line, timeout) {
+            Ok(reply) => Ok(reply),
+            Err(_) => {
+                // Whatever was in flight is lost with the connection.
+                self.conn = None;
+                Err(Error::Disconnected)
+            },
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.conn = None;
+        let mut delay = Duration::from_millis(100);
+        for attempt in 0..self.retries.max(1) {
+            if attempt > 0 {
+                thread::sleep(delay);
+                delay = (delay * 2).min(Duration::from_secs(5));
+            }
+            match Connection::open(&self.addrs[..], self.events_tx.clone(), self.timeout) {
+                Ok(conn) => {
+                    self.conn = Some(conn);
+                    if let Some((email, password)) = self.credentials.clone() {
+                        let reply = self.request(&format!("login {} {}", email, password))?;
+                        reply.map_err(|e| Error::from_reply(&e))?;
+                    }
+                    let _ = self.events_tx.send(Event::Reconnected);
+                    return Ok(());
+                },
+                // A server that is down, restarting or full may take us back later.
+                Err(Error::Io(_)) | Err(Error::Disconnected) | Err(Error::Busy) | Err(Error::ShuttingDown) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::Disconnected)
+    }
+}
+
+// Tab separated rows of a table reply, after its `===` underline.
+fn rows(reply :&str) -> impl Iterator<Item = Vec<&str>> {
+    reply.lines()
+        .skip_while(|l| !l.starts_with("==="))
+        .skip(1)
+        .filter(|l| !l.is_empty())
+        .map(|l| l.split('\t').collect())
+}
+
+fn offer(row :&[&str]) -> Option<Offer> {
+    match *row {
+        [st, available, price] => Some(Offer {
+            server_type: ServerType::parse(st)?,
+            available: available.parse().ok()?,
+            price: price.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+fn quota(row :&[&str]) -> Option<QuotaInfo> {
+    match *row {
+        [name, used, limit, left] => Some(QuotaInfo {
+            name: name.to_owned(),
+            used: used.parse().ok()?,
+            limit: limit.parse().ok()?,
+            left: left.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+fn droplet(row :&[&str]) -> Option<DropletInfo> {
+    match *row {
+        [id, st, state, up, expires, owner] => Some(DropletInfo {
+            id: DropletId::parse(id)?,
+            server_type: ServerType::parse(st)?,
+            state: DropletState::parse(state)?,
+            uptime: if up == "-" { None } else { Some(uptime(up)?) },
+            expires: if expires == "-" { None } else { Some(expires.to_owned()) },
+            owner: owner.to_owned(),
+        }),
+        _ => None,
+    }
+}
+
+// Whole minutes, rounded up, as the server reads lease lengths.
+fn length(d :Duration) -> String {
+    format!("{}m", d.as_secs().div_ceil(60).max(1))
+}
+
+// Parses the `1h02m03s` uptime format.
+fn uptime(s :&str) -> Option<Duration> {
+    let (h, rest) = s.split_at(s.find('h')?);
+    let (m, rest) = rest[1..].split_at(rest.find('m')? - 1);
+    let sec = rest[1..].strip_suffix('s')?;
+    let secs = h.parse::<u64>().ok()? * 3600 + m.parse::<u64>().ok()? * 60 + sec.parse::<u64>().ok()?;
+    Some(Duration::from_secs(secs))
+}
@@ -1,19 +1,46 @@
 pub mod server_type;
 pub mod client;
-mod droplet;
+pub mod droplet;
 pub mod bid;
 mod auction;
+mod snapshot;
+mod timed_lock;
+mod unique_bid_queue;
+pub mod lockout;
+pub mod audit;
+pub mod pricing;
+pub mod history;
+pub mod quota;
+pub mod org;
 
 use self::client::Client;
-use self::droplet::Droplet;
+use self::droplet::{Droplet, DropletId, DropletState};
 use self::server_type::ServerType;
 use self::bid::Bid;
 use self::auction::Auction;
+use self::lockout::{Lockouts, LockoutPolicy, LockoutState};
+use self::timed_lock::TimedRwLock;
+use self::audit::AuditLog;
+use self::pricing::{Pricing, PricingPolicy};
+use self::history::{AuctionRecord, History, Outcome};
+use self::quota::{Quota, QuotaPolicy};
+use self::org::{Org, OrgSummary, Role};
+use self::unique_bid_queue::UniqueBidQueue;
+use crate::clock::{Clock, TimerId};
 
-use std::sync::RwLock;
-use std::sync::Arc;
+use chrono::{DateTime, Local};
+
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock, Weak};
 use std::collections::HashMap;
-use std::sync::atomic::AtomicUsize;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+pub const AUCTION_DURATION :usize = 60;
+
+// How long before a lease ends its owner is warned, in seconds.
+pub const LEASE_WARNING :usize = 600;
 
 #[derive(Debug)]
 pub enum AHouseError {
@@ -22,14 +49,89 @@ pub enum AHouseError {
     EmailTaken(String),
     LockError(String),
     BidTooLow(i32),
-    QueueInterrupted,
+    InvalidBid(i32),
+    PermissionDenied(String),
+    Suspended(String),
+    InvalidDroplet(DropletId),
+    InvalidState(DropletId, DropletState),
+    NoAuction(ServerType),
+    NoLease(DropletId),
+    QuotaExceeded(Quota),
+    NoOrg(String),
+    OrgTaken(String),
+    // The org, then the email.
+    NotMember(String, String),
+    AlreadyMember(String, String),
+    NoInvite(String),
+    LastOwner(String),
+    TooManyAttempts(Duration),
 }
 
+#[derive(Debug)]
 pub enum AuctionKind {
-    TimedStarted,
-    TimedRebided,
-    QueueDroppped,
-    QueueGranted,
+    TimedStarted(usize),
+    TimedRebided(i32),
+    TimedOutbid(i32),
+    TimedWon(DropletId),
+    TimedLost(i32),
+    TimedCancelled,
+    Queued(usize),
+    QueueDroppped(usize),
+    QueueGranted(DropletId),
+    // The top bid won or was next in the queue, but the droplet would have put
+    // its account over a quota.
+    TimedOverQuota,
+    QueueOverQuota,
+}
+
+impl AuctionKind {
+    pub fn message(&self, server_type :ServerType) -> String {
+        match self {
+            AuctionKind::TimedStarted(delay) =>
+                format!("{:?} auction started, closing in {}s", server_type, delay),
+            AuctionKind::TimedRebided(value) =>
+                format!("Bid of {} is now the top {:?} bid", value, server_type),
+            AuctionKind::TimedOutbid(value) =>
+                format!("Outbid on the {:?} auction, top bid is now {}", server_type, value),
+            AuctionKind::TimedWon(id) =>
+                format!("Won the {:?} auction, droplet {}", server_type, id),
+            AuctionKind::TimedLost(value) =>
+                format!("Lost the {:?} auction, winning bid was {}", server_type, value),
+            AuctionKind::TimedCancelled =>
+                format!("{:?} auction cancelled by an admin", server_type),
+            AuctionKind::Queued(position) =>
+                format!("{:?} out of stock, bid queued at position {}", server_type, position),
+            AuctionKind::QueueDroppped(position) =>
+                format!("Earlier {:?} bid dropped, new bid queued at position {}", server_type, position),
+            AuctionKind::QueueGranted(id) =>
+                format!("Queued {:?} bid granted, droplet {}", server_type, id),
+            AuctionKind::TimedOverQuota =>
+                format!("Won the {:?} auction over quota, droplet released", server_type),
+            AuctionKind::QueueOverQuota =>
+                format!("Queued {:?} bid dropped, it is over quota", server_type),
+        }
+    }
+}
+
+// Sends a message to every session the client is logged in on.
+type NotifyFn = Box<dyn Fn(&str, &str) + Send + Sync>;
+
+#[derive(Default)]
+struct Notifier(RwLock<Option<NotifyFn>>);
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f :&mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Notifier")
+    }
+}
+
+// `id` is None when the bid was dropped for being over quota.
+#[derive(Debug)]
+struct Grant {
+    server_type :ServerType,
+    bid :Bid,
+    id :Option<DropletId>,
+    expires :Option<DateTime<Local>>,
 }
 
 impl<T> From<std::sync::PoisonError<T>> for AHouseError {
@@ -48,160 +150,1523 @@ impl From<self::auction::BidError> for AHouseError {
     }
 }
 
+// Where the units of one server type are. Every unit is in stock, held as a
+// droplet by a client or an org, or taken by a running auction; queued bids hold
+// none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeTotals {
+    pub server_type :ServerType,
+    pub stock :u32,
+    pub held :usize,
+    pub auctions :usize,
+    pub queued :usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub stock :Vec<(ServerType, u32)>,
+    pub reserved_d :Vec<(ServerType, usize)>,
+    pub reserved_a :Vec<(ServerType, usize)>,
+    pub auctions :usize,
+    pub queues :Vec<(ServerType, usize)>,
+    pub prices :Vec<(ServerType, i32)>,
+    pub clients :usize,
+    pub locks :Vec<(&'static str, u64, Duration)>,
+    pub recoveries :Vec<(&'static str, u64)>,
+}
+
+#[derive(Debug, Default)]
+struct Market {
+    stock      :HashMap<ServerType, u32>,
+    auctions   :HashMap<ServerType, Auction>,
+    queues     :HashMap<ServerType, UniqueBidQueue>,
+    reserved_a :HashMap<DropletId,  Droplet>,
+    reserved_d :HashMap<DropletId,  Droplet>,
+    billed     :HashMap<String,     f64>,
+    spent      :HashMap<String,     Vec<(DateTime<Local>, f64)>>,
+    pricing    :Pricing,
+    history    :History,
+    quotas     :QuotaPolicy,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Power {
+    Start,
+    Stop,
+    Reboot,
+}
+
+impl Market {
+    fn take(&mut self, server_type :ServerType) -> Result<(), AHouseError> {
+        match self.stock.get_mut(&server_type) {
+            Some(v) if *v > 0 => { *v -= 1; Ok(()) },
+            _ => Err(AHouseError::OutOfStock(server_type)),
+        }
+    }
+
+    // A released droplet goes to the highest queued bid before it goes back to stock.
+    // Bids it would put over a quota are dropped on the way.
+    fn release(&mut self, server_type :ServerType, now :DateTime<Local>) -> Vec<Grant> {
+        let mut grants = Vec::new();
+        while let Some(bid) = self.queues.get_mut(&server_type).and_then(|q| q.pop()) {
+            if self.check_quota(bid.account(), server_type, bid.value(), false, now).is_err() {
+                grants.push(Grant { server_type, bid, id: None, expires: None });
+                continue
+            }
+            let droplet = Droplet::new(server_type, bid.account(), bid.value(), now);
+            let id = droplet.id();
+            let expires = bid.lease().map(|lease| now + lease);
+            self.reserved_a.insert(id, droplet);
+            self.get_mut(id).unwrap().set_expires(expires);
+            grants.push(Grant { server_type, bid, id: Some(id), expires });
+            return grants
+        }
+        *self.stock.entry(server_type).or_insert(0) += 1;
+        grants
+    }
+
+    // Run on a poisoned market before it is handed out again: drops droplets filed
+    // under the wrong id or reserved twice, and returns how many were dropped. The
+    // droplets that were only filed wrong go back to stock; with no clock here,
+    // queued bids get them on the next release.
+    fn check(market :&mut Market) -> usize {
+        let before = market.reserved_d.len() + market.reserved_a.len();
+        let mut misfiled = HashMap::new();
+        for reserved in [&mut market.reserved_d, &mut market.reserved_a] {
+            reserved.retain(|id, d| {
+                if *id != d.id() { misfiled.insert(d.id(), d.server_type()); }
+                *id == d.id()
+            });
+        }
+        let reserved_d = &market.reserved_d;
+        market.reserved_a.retain(|id, _| !reserved_d.contains_key(id));
+        for (id, server_type) in misfiled {
+            if market.get(id).is_none() {
+                *market.stock.entry(server_type).or_insert(0) += 1;
+            }
+        }
+        before - market.reserved_d.len() - market.reserved_a.len()
+    }
+
+    fn get(&self, id :DropletId) -> Option<&Droplet> {
+        self.reserved_d.get(&id).or_else(|| self.reserved_a.get(&id))
+    }
+
+    fn get_mut(&mut self, id :DropletId) -> Option<&mut Droplet> {
+        match self.reserved_d.get_mut(&id) {
+            Some(d) => Some(d),
+            None => self.reserved_a.get_mut(&id),
+        }
+    }
+
+    // Terminates the droplet, books what it ran up against its owner and puts it
+    // back on the market.
+    fn terminate(&mut self, id :DropletId, now :DateTime<Local>) -> Option<(Droplet, Vec<Grant>)> {
+        let mut droplet = self.reserved_d.remove(&id).or_else(|| self.reserved_a.remove(&id))?;
+        let schedule = self.pricing.schedule(droplet.server_type());
+        droplet.terminate(now, schedule);
+        let charges = droplet.charges(now, schedule);
+        *self.billed.entry(droplet.owner().to_owned()).or_insert(0.0) += charges;
+        // Only what the spend quota's window can still see is kept.
+        match self.quotas.spend {
+            Some((_, period)) => {
+                let spent = self.spent.entry(droplet.owner().to_owned()).or_default();
+                spent.retain(|(t, _)| *t > now - period);
+                spent.push((now, charges));
+            },
+            None => { self.spent.remove(droplet.owner()); },
+        }
+        let grant = self.release(droplet.server_type(), now);
+        Some((droplet, grant))
+    }
+
+    // The hourly rate a droplet bought now keeps. Running auctions count as in use.
+    fn price(&self, server_type :ServerType) -> i32 {
+        let used = self.reserved_d.values()
+            .chain(self.reserved_a.values())
+            .filter(|d| d.server_type() == server_type)
+            .count()
+            + self.auctions.contains_key(&server_type) as usize;
+        let stock = self.stock.get(&server_type).cloned().unwrap_or(0) as usize;
+        self.pricing.price(server_type, used, used + stock)
+    }
+
+    // What that rate comes to at `t` on the type's schedule.
+    fn listed(&self, server_type :ServerType, t :DateTime<Local>) -> i32 {
+        self.pricing.scheduled(server_type, self.price(server_type), t)
+    }
+
+    fn closed(&mut self, server_type :ServerType, auction :&Auction, outcome :Outcome, now :DateTime<Local>) {
+        let bid = auction.top_bid();
+        self.history.record(AuctionRecord {
+            server_type,
+            outcome,
+            bidder: bid.account().to_owned(),
+            price: bid.value(),
+            bids: auction.bid_count(),
+            started: auction.started(),
+            closed: now,
+        });
+    }
+
+    // Each configured quota with how much of it the client uses now.
+    fn quota_use(&self, clt :&str, now :DateTime<Local>) -> Vec<(Quota, f64)> {
+        self.quotas.quotas().into_iter().map(|quota| {
+            let used = match quota {
+                Quota::Droplets(st, _) => self.owned_by(clt).filter(|d| d.server_type() == st).count() as f64,
+                Quota::Spend(_, period) => {
+                    let from = now - period;
+                    self.spent.get(clt).map_or(0.0, |s| s.iter().filter(|(t, _)| *t > from).map(|(_, a)| a).sum())
+                        + self.owned_by(clt)
+                            .map(|d| d.charges_since(from, now, self.pricing.schedule(d.server_type())))
+                            .sum::<f64>()
+                },
+                Quota::Wins(_, window) => self.history.wins(clt, now - window) as f64,
+            };
+            (quota, used)
+        }).collect()
+    }
+
+    // Whether the client may take one more droplet of the type at `rate` an hour:
+    // the spend quota needs room for its first hour. `auction` also counts it as one
+    // more auction win.
+    fn check_quota(&self, clt :&str, server_type :ServerType, rate :i32, auction :bool, now :DateTime<Local>)
+        -> Result<(), AHouseError> {
+        for (quota, used) in self.quota_use(clt, now) {
+            let over = match quota {
+                Quota::Droplets(st, n) => st == server_type && used as usize >= n,
+                Quota::Spend(limit, _) => used + rate as f64 > limit,
+                Quota::Wins(n, _) => auction && used as usize >= n,
+            };
+            if over {
+                return Err(AHouseError::QuotaExceeded(quota))
+            }
+        }
+        Ok(())
+    }
+
+    fn owned_by(&self, clt :&str) -> impl Iterator<Item = &Droplet> {
+        let clt = clt.to_owned();
+        self.reserved_d.values()
+            .chain(self.reserved_a.values())
+            .filter(move |d| d.owner() == clt)
+    }
+}
+
+// The role `clt` holds over what `account` owns; all of it when it is their own.
+fn role(orgs :&HashMap<String, Org>, clt :&str, account :&str) -> Option<Role> {
+    if clt == account {
+        Some(Role::Owner)
+    } else {
+        orgs.get(account).and_then(|o| o.role(clt))
+    }
+}
+
+fn check_role(orgs :&HashMap<String, Org>, clt :&str, org :&str, needed :Role) -> Result<(), AHouseError> {
+    match orgs.get(org) {
+        None => Err(AHouseError::NoOrg(org.into())),
+        Some(o) if o.role(clt) >= Some(needed) => Ok(()),
+        Some(_) => Err(AHouseError::PermissionDenied(format!("{} in {}", clt, org))),
+    }
+}
+
+// Every change to stock, auctions, queues and reserved droplets happens under the
+// single `market` guard, so readers never see a droplet in two places or in none.
+// Locks are always taken in the order `clients`, `orgs`, `market`.
+// `this` lets timers started from `&self` reach the house without keeping it alive.
+// `leases` holds the pending warning and expiry timers of each leased droplet.
 #[derive(Debug)]
 pub struct AuctionHouse {
-    stock           :RwLock<HashMap<ServerType, u32>>,
-    auctions        :RwLock<HashMap<ServerType, Auction>>,
-    queues          :RwLock<HashMap<ServerType, UniqueBidQueue>,
-    reserved_a      :RwLock<HashMap<u32,        Droplet>>,
-    reserved_d      :RwLock<HashMap<u32,        Droplet>>,
-    clients         :RwLock<HashMap<String,     Client>>,
-    dropped_servers :RwLock<HashMap<String,     AtomicUsize>>,
+    market          :TimedRwLock<Market>,
+    clients         :TimedRwLock<HashMap<String,     Client>>,
+    orgs            :TimedRwLock<HashMap<String,     Org>>,
+    lockouts        :Lockouts,
+    audit           :AuditLog,
+    notifier        :Notifier,
+    auction_duration :AtomicUsize,
+    lease_warning   :AtomicUsize,
+    leases          :Mutex<HashMap<DropletId, Vec<TimerId>>>,
+    clock           :Arc<dyn Clock>,
+    this            :Weak<AuctionHouse>,
 }
 
 impl AuctionHouse {
-    pub fn new() -> Self {
-        AuctionHouse {
-            stock :RwLock::new(HashMap::new()),
-            auctions :RwLock::new(HashMap::new()),
-            queues :RwLock::new(HashMap::new()),
-            reserved_a :RwLock::new(HashMap::new()),
-            reserved_d :RwLock::new(HashMap::new()),
-            clients :RwLock::new(HashMap::new()),
-            dropped_servers :RwLock::new(HashMap::new()),
-        }
+    pub fn new(clock :Arc<dyn Clock>) -> Arc<Self> {
+        Arc::new_cyclic(|this| AuctionHouse {
+            market :TimedRwLock::with_check(Market::default(), Market::check),
+            clients :TimedRwLock::with_check(HashMap::new(), |clients| {
+                let before = clients.len();
+                clients.retain(|email, c| email == c.email());
+                before - clients.len()
+            }),
+            orgs :TimedRwLock::with_check(HashMap::new(), |orgs| {
+                let before = orgs.len();
+                orgs.retain(|name, o| name == o.name());
+                before - orgs.len()
+            }),
+            lockouts :Lockouts::default(),
+            audit :AuditLog::default(),
+            notifier :Notifier::default(),
+            auction_duration :AtomicUsize::new(AUCTION_DURATION),
+            lease_warning :AtomicUsize::new(LEASE_WARNING),
+            leases :Mutex::new(HashMap::new()),
+            clock,
+            this: this.clone(),
+        })
     }
 
-    pub fn ls(&self) -> Vec<(ServerType, u32)> {
-        self.stock.read().unwrap()
+    pub fn now(&self) -> DateTime<Local> {
+        self.clock.now()
+    }
+
+    // Stock and current price of each type.
+    pub fn ls(&self) -> Vec<(ServerType, u32, i32)> {
+        let now = self.now();
+        let market = self.market.read();
+        market.stock
             .iter()
-            .map(|(k, v)| (*k, *v))
+            .map(|(k, v)| (*k, *v, market.listed(*k, now)))
             .collect()
     }
 
-    pub fn ls_m(&self, clt :&str) -> Vec<Droplet> {
-        self.reserved_d.read().unwrap().values().filter(|d| d.owner() == clt).cloned().collect()
+    // Each type's price now and at every scheduled change in the coming week, at
+    // today's demand.
+    pub fn prices(&self) -> Vec<(ServerType, DateTime<Local>, u32, i32)> {
+        let now = self.now();
+        let market = self.market.read();
+        let mut types = market.stock.keys().cloned().collect::<Vec<_>>();
+        types.sort();
+        let mut prices = Vec::new();
+        for st in types {
+            let schedule = market.pricing.schedule(st);
+            let mut t = Some(now);
+            while let Some(at) = t.filter(|at| *at < now + chrono::Duration::days(7)) {
+                prices.push((st, at, schedule.percent(at), market.listed(st, at)));
+                t = schedule.next_change(at);
+            }
+        }
+        prices
     }
 
-    pub fn buy(ah :Arc<AuctionHouse>, sv_tp :ServerType, clt :&str) -> Result<u32, AHouseError> {
-        if !ah.clients.read()?.contains_key(clt) {
-            return Err(AHouseError::InvalidClient(clt.into()))
+    pub fn stats(&self) -> Stats {
+        let by_type = |reserved :&HashMap<DropletId, Droplet>| {
+            let mut counts = HashMap::new();
+            for d in reserved.values() {
+                *counts.entry(d.server_type()).or_insert(0) += 1;
+            }
+            counts.into_iter().collect()
         };
-        let mut stock = ah.stock.write().unwrap();
-        match stock.get_mut(&sv_tp) {
-            None => Err(AHouseError::OutOfStock(sv_tp)),
-            Some(v) => {
-                if *v == 0 {
-                    Err(AHouseError::OutOfStock(sv_tp))
-                }else{
-                    let mut reserved = ah.reserved_d.write().unwrap();
-                    let new_drop = Droplet::new_reserved(sv_tp, clt);
-                    let id = new_drop.id();
-                    reserved.insert(id, new_drop);
-                    *v -= 1;
-                    Ok(id)
-                }
+        let now = self.now();
+        let clients = self.clients.read().len();
+        let market = self.market.read();
+        Stats {
+            stock: market.stock.iter().map(|(k, v)| (*k, *v)).collect(),
+            reserved_d: by_type(&market.reserved_d),
+            reserved_a: by_type(&market.reserved_a),
+            auctions: market.auctions.len(),
+            queues: market.queues.iter().map(|(st, q)| (*st, q.len())).collect(),
+            prices: market.stock.keys().map(|st| (*st, market.listed(*st, now))).collect(),
+            clients,
+            locks: vec![
+                ("market", self.market.stats()),
+                ("clients", self.clients.stats()),
+                ("orgs", self.orgs.stats()),
+            ].into_iter().map(|(name, (n, wait))| (name, n, wait)).collect(),
+            recoveries: vec![
+                ("market", self.market.recoveries()),
+                ("clients", self.clients.recoveries()),
+                ("orgs", self.orgs.recoveries()),
+            ],
+        }
+    }
+
+    pub fn healthy(&self) -> bool {
+        self.market.recoveries() == 0 && self.clients.recoveries() == 0 && self.orgs.recoveries() == 0
+    }
+
+    pub fn dump(&self) -> String {
+        let clients = self.clients.read();
+        let market = self.market.read();
+        let mut out = String::from("[stock]\n");
+        for (st, n) in market.stock.iter() {
+            out += &format!("{:?}\t{}\n", st, n);
+        }
+        out += "[clients]\n";
+        for c in clients.values() {
+            out += &format!("{}\tadmin={}\tsuspended={}\n", c.email(), c.is_admin(), c.is_suspended());
+        }
+        out += "[droplets]\n";
+        let now = self.now();
+        for (kind, reserved) in [("reserved", &market.reserved_d), ("auctioned", &market.reserved_a)].iter() {
+            for d in reserved.values() {
+                out += &format!("{}\t{:?}\t{}\t{}\t{}\t{}\n",
+                                d.id(), d.server_type(), d.owner(), d.value(), kind, d.state(now).name());
             }
         }
+        out += "[queues]\n";
+        for (st, q) in market.queues.iter().filter(|(_, q)| !q.is_empty()) {
+            for bid in q.iter() {
+                out += &format!("{:?}\t{}\t{}\n", st, bid.owner(), bid.value());
+            }
+        }
+        out += "[auctions]\n";
+        for (st, a) in market.auctions.iter() {
+            let bid = a.top_bid();
+            out += &format!("{:?}\t{}\t{}\t{}s left\n", st, bid.owner(), bid.value(), a.time_left());
+        }
+        out
+    }
+
+    // Auctions of the type that closed at or after `from`, newest first.
+    pub fn history(&self, server_type :ServerType, from :DateTime<Local>) -> Vec<AuctionRecord> {
+        self.market.read().history.since(server_type, from)
+    }
+
+    // The client's own droplets and those of every org they are a member of.
+    pub fn ls_m(&self, clt :&str) -> Vec<Droplet> {
+        let orgs = self.orgs.read();
+        let market = self.market.read();
+        let mut droplets = market.reserved_d.values()
+            .chain(market.reserved_a.values())
+            .filter(|d| role(&orgs, clt, d.owner()).is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        droplets.sort_by_key(|d| d.id());
+        droplets
+    }
+
+    // The droplet keeps the rate it was bought at, however demand moves later, and
+    // accrues it on the type's schedule. A purchaser of `org` can buy for it instead
+    // of themselves. Returns the price listed at purchase.
+    pub fn buy(ah :Arc<AuctionHouse>, sv_tp :ServerType, clt :&str, org :Option<&str>, lease :Option<chrono::Duration>)
+        -> Result<(DropletId, i32), AHouseError> {
+        let clients = ah.clients.read();
+        match clients.get(clt) {
+            None => return Err(AHouseError::InvalidClient(clt.into())),
+            Some(c) if c.is_suspended() => return Err(AHouseError::Suspended(clt.into())),
+            Some(_) => (),
+        };
+        let orgs = ah.orgs.read();
+        if let Some(org) = org {
+            check_role(&orgs, clt, org, Role::Purchaser)?;
+        }
+        let account = org.unwrap_or(clt);
+        let mut market = ah.market.write();
+        let now = ah.now();
+        let (price, listed) = (market.price(sv_tp), market.listed(sv_tp, now));
+        market.check_quota(account, sv_tp, price, false, now)?;
+        market.take(sv_tp)?;
+        let mut new_drop = Droplet::new(sv_tp, account, price, now);
+        let id = new_drop.id();
+        let expires = lease.map(|lease| now + lease);
+        new_drop.set_expires(expires);
+        market.reserved_d.insert(id, new_drop);
+        drop(market);
+        drop(orgs);
+        drop(clients);
+        if let Some(expires) = expires {
+            ah.lease(id, expires);
+        }
+        Ok((id, listed))
     }
 
     pub fn add(&self, server_type :ServerType) {
-        self.stock
-            .write().unwrap()
-            .entry(server_type)
-            .and_modify(|v| *v += 1)
-            .or_insert(1);
+        let grant = self.market.write().release(server_type, self.now());
+        self.granted(grant);
+    }
+
+    pub fn set_notifier<F>(&self, f :F)
+        where F: Fn(&str, &str) + Send + Sync + 'static {
+        *self.notifier.0.write().unwrap() = Some(Box::new(f));
+    }
+
+    fn notify(&self, clt :&str, msg :&str) {
+        if let Some(f) = self.notifier.0.read().unwrap().as_ref() {
+            f(clt, msg);
+        }
+    }
+
+    // Notices about an org's droplets go to the members who can act on them.
+    fn notify_account(&self, account :&str, msg :&str) {
+        let members = match self.orgs.read().get(account) {
+            Some(o) => o.members()
+                .into_iter()
+                .filter(|(_, role, invited)| !invited && *role >= Role::Purchaser)
+                .map(|(email, _, _)| email)
+                .collect(),
+            None => vec![account.to_owned()],
+        };
+        for m in members {
+            self.notify(&m, msg);
+        }
+    }
+
+    fn granted<I :IntoIterator<Item = Grant>>(&self, grants :I) {
+        for g in grants {
+            let (st, value) = (format!("{:?}", g.server_type), g.bid.value().to_string());
+            let args = [st.as_str(), value.as_str()];
+            let id = match g.id {
+                Some(id) => id,
+                None => {
+                    self.audit.record(None, Some(g.bid.owner()), "queue-drop", &args, "over quota");
+                    self.notify(g.bid.owner(), &AuctionKind::QueueOverQuota.message(g.server_type));
+                    continue
+                },
+            };
+            if let Some(expires) = g.expires {
+                self.lease(id, expires);
+            }
+            self.audit.record(None, Some(g.bid.owner()), "queue-grant", &args, &format!("droplet {}", id));
+            self.notify(g.bid.owner(), &AuctionKind::QueueGranted(id).message(g.server_type));
+        }
+    }
+
+    pub fn set_pricing(&self, policy :PricingPolicy) {
+        self.market.write().pricing.set_policy(policy);
+    }
+
+    pub fn set_quotas(&self, policy :QuotaPolicy) {
+        self.market.write().quotas = policy;
+    }
+
+    // Each configured quota, what the client or an org they are in uses of it and
+    // what is left. Members of an org share its quotas.
+    pub fn quota(&self, clt :&str, org :Option<&str>) -> Result<Vec<(Quota, f64, f64)>, AHouseError> {
+        if let Some(org) = org {
+            check_role(&self.orgs.read(), clt, org, Role::Viewer)?;
+        }
+        Ok(self.market.read()
+           .quota_use(org.unwrap_or(clt), self.now())
+           .into_iter()
+           .map(|(quota, used)| (quota, used, (quota.limit() - used).max(0.0)))
+           .collect())
+    }
+
+    pub fn set_lease_warning(&self, secs :usize) {
+        self.lease_warning.store(secs, Ordering::Relaxed);
+    }
+
+    // Warns the owner ahead of `expires` and releases the droplet then. A renewal
+    // cancels the timers set for the old `expires`.
+    fn lease(&self, id :DropletId, expires :DateTime<Local>) {
+        let now = self.now();
+        let warning = chrono::Duration::seconds(self.lease_warning.load(Ordering::Relaxed) as i64);
+        let until = |t :DateTime<Local>| (t - now).to_std().unwrap_or_default();
+        let mut timers = Vec::new();
+        if expires - warning > now {
+            let this = self.this.clone();
+            timers.push(self.clock.schedule(until(expires - warning), Box::new(move || {
+                if let Some(ah) = this.upgrade() { ah.lease_ending(id, expires) }
+            })));
+        }
+        let this = self.this.clone();
+        timers.push(self.clock.schedule(until(expires), Box::new(move || {
+            if let Some(ah) = this.upgrade() { ah.lease_ended(id, expires) }
+        })));
+        let old = self.leases.lock().unwrap().insert(id, timers);
+        for timer in old.into_iter().flatten() {
+            self.clock.cancel(timer);
+        }
+    }
+
+    // Cancels the timers of a droplet that is gone.
+    fn unlease(&self, id :DropletId) {
+        let timers = self.leases.lock().unwrap().remove(&id);
+        for timer in timers.into_iter().flatten() {
+            self.clock.cancel(timer);
+        }
+    }
+
+    // Starts the timers of restored leases. One that ran out while the server was
+    // down ends right away.
+    pub fn resume_leases(&self) {
+        let leases = {
+            let market = self.market.read();
+            market.reserved_d.values()
+                .chain(market.reserved_a.values())
+                .filter_map(|d| d.expires().map(|expires| (d.id(), expires)))
+                .collect::<Vec<_>>()
+        };
+        for (id, expires) in leases {
+            self.lease(id, expires);
+        }
+    }
+
+    fn lease_ending(&self, id :DropletId, expires :DateTime<Local>) {
+        let owner = match self.market.read().get(id) {
+            Some(d) if d.expires() == Some(expires) => d.owner().to_owned(),
+            _ => return,
+        };
+        let left = ((expires - self.now()).num_milliseconds() + 999).max(0) / 1000;
+        self.notify_account(&owner, &format!("Lease ending in {}s, renew to keep droplet {}", left, id));
+    }
+
+    fn lease_ended(&self, id :DropletId, expires :DateTime<Local>) {
+        let mut market = self.market.write();
+        match market.get(id) {
+            Some(d) if d.expires() == Some(expires) => (),
+            _ => return,
+        }
+        let (droplet, grant) = market.terminate(id, self.now()).unwrap();
+        drop(market);
+        self.unlease(id);
+        self.audit.record(None, Some(droplet.owner()), "lease-expire", &[&id.to_string()], "released");
+        self.notify_account(droplet.owner(), &format!("Lease ended, released droplet {}", id));
+        self.granted(grant);
+    }
+
+    // Pushes the end of the lease back by `by`; returns when it now ends.
+    pub fn renew(&self, ctl :&str, id :DropletId, by :chrono::Duration) -> Result<DateTime<Local>, AHouseError> {
+        let orgs = self.orgs.read();
+        let mut market = self.market.write();
+        let droplet = match market.get_mut(id) {
+            Some(d) if role(&orgs, ctl, d.owner()) >= Some(Role::Purchaser) => d,
+            _ => return Err(AHouseError::InvalidDroplet(id)),
+        };
+        let expires = droplet.expires().ok_or(AHouseError::NoLease(id))? + by;
+        droplet.set_expires(Some(expires));
+        drop(market);
+        drop(orgs);
+        self.lease(id, expires);
+        Ok(expires)
+    }
+
+    pub fn create_org(&self, clt :&str, name :&str) -> Result<(), AHouseError> {
+        let clients = self.clients.read();
+        let mut orgs = self.orgs.write();
+        if clients.contains_key(name) || orgs.contains_key(name) {
+            return Err(AHouseError::OrgTaken(name.into()))
+        }
+        orgs.insert(name.to_owned(), Org::new(name, clt));
+        Ok(())
+    }
+
+    // Owners invite; the invited client becomes a member once they join.
+    pub fn invite(&self, clt :&str, org :&str, email :&str, role :Role) -> Result<(), AHouseError> {
+        let clients = self.clients.read();
+        if !clients.contains_key(email) {
+            return Err(AHouseError::InvalidClient(email.into()))
+        }
+        let mut orgs = self.orgs.write();
+        check_role(&orgs, clt, org, Role::Owner)?;
+        let o = orgs.get_mut(org).unwrap();
+        if o.role(email).is_some() {
+            return Err(AHouseError::AlreadyMember(org.into(), email.into()))
+        }
+        o.invite(email, role);
+        drop(orgs);
+        drop(clients);
+        self.notify(email, &format!("Invited to {} as {}, org-join {} to accept", org, role.name(), org));
+        Ok(())
+    }
+
+    pub fn join_org(&self, clt :&str, org :&str) -> Result<Role, AHouseError> {
+        self.orgs.write()
+            .get_mut(org)
+            .ok_or_else(|| AHouseError::NoOrg(org.into()))?
+            .join(clt)
+            .ok_or_else(|| AHouseError::NoInvite(org.into()))
+    }
+
+    // Removes a member or declines an invite: owners can remove anyone, others only
+    // themselves. The last owner has to hand the org over first.
+    pub fn remove_member(&self, clt :&str, org :&str, email :&str) -> Result<(), AHouseError> {
+        let mut orgs = self.orgs.write();
+        let o = orgs.get_mut(org).ok_or_else(|| AHouseError::NoOrg(org.into()))?;
+        if clt != email && o.role(clt) != Some(Role::Owner) {
+            return Err(AHouseError::PermissionDenied(format!("{} in {}", clt, org)))
+        }
+        if o.role(email) == Some(Role::Owner) && o.owners() == 1 {
+            return Err(AHouseError::LastOwner(org.into()))
+        }
+        if !o.remove(email) {
+            return Err(AHouseError::NotMember(org.into(), email.into()))
+        }
+        Ok(())
+    }
+
+    pub fn set_role(&self, clt :&str, org :&str, email :&str, role :Role) -> Result<(), AHouseError> {
+        let mut orgs = self.orgs.write();
+        check_role(&orgs, clt, org, Role::Owner)?;
+        let o = orgs.get_mut(org).unwrap();
+        if o.role(email) == Some(Role::Owner) && role != Role::Owner && o.owners() == 1 {
+            return Err(AHouseError::LastOwner(org.into()))
+        }
+        if !o.set_role(email, role) {
+            return Err(AHouseError::NotMember(org.into(), email.into()))
+        }
+        Ok(())
+    }
+
+    // The orgs the client is a member of or invited to, by name.
+    pub fn orgs(&self, clt :&str) -> Vec<(String, Role, bool)> {
+        let mut list = self.orgs.read()
+            .values()
+            .filter_map(|o| match (o.role(clt), o.invited(clt)) {
+                (Some(role), _) => Some((o.name().to_owned(), role, false)),
+                (None, Some(role)) => Some((o.name().to_owned(), role, true)),
+                (None, None) => None,
+            })
+            .collect::<Vec<_>>();
+        list.sort();
+        list
+    }
+
+    pub fn org(&self, clt :&str, org :&str) -> Result<OrgSummary, AHouseError> {
+        let members = {
+            let orgs = self.orgs.read();
+            check_role(&orgs, clt, org, Role::Viewer)?;
+            orgs[org].members()
+        };
+        let droplets = self.market.read().owned_by(org).count();
+        Ok(OrgSummary { members, droplets, charges: self.charges(org) })
+    }
+
+    pub fn set_auction_duration(&self, secs :usize) {
+        self.auction_duration.store(secs, Ordering::Relaxed);
     }
 
     pub fn register(&self, email :&str, password :&str) -> Result<Client, AHouseError> {
-        let mut clients = self.clients.write().unwrap();
-        if clients.contains_key(email) {
+        self.add_client(email, password, Client::new)
+    }
+
+    pub fn register_admin(&self, email :&str, password :&str) -> Result<Client, AHouseError> {
+        self.add_client(email, password, Client::new_admin)
+    }
+
+    fn add_client(&self, email :&str, password :&str, new :fn(String, String) -> Client)
+        -> Result<Client, AHouseError> {
+        let mut clients = self.clients.write();
+        if clients.contains_key(email) || self.orgs.read().contains_key(email) {
             Err(AHouseError::EmailTaken(email.to_string()))
         }else{
-            let client = Client::new(email.to_string(), password.to_string());
+            let client = new(email.to_string(), password.to_string());
             clients.insert(email.to_string(), client.clone());
             Ok(client)
         }
     }
 
-    pub fn login(&self, email: &str, password :&str) -> Result<Client, AHouseError> {
-        self.clients.read()?
-            .get(email)
-            .cloned()
-            .map(|c| Ok(c))
-            .unwrap_or(Err(AHouseError::InvalidClient(email.into())))
-            .and_then(|c|
-                      if c.password() == password {
-                          Ok(c)
-                      } else {
-                          Err(AHouseError::InvalidClient(email.into()))
-                      }
-                     )
+    pub fn login(&self, email: &str, password :&str, source :Option<IpAddr>) -> Result<Client, AHouseError> {
+        self.lockouts.check(email, source).map_err(AHouseError::TooManyAttempts)?;
+        let client = self.clients.read().get(email).cloned();
+        match client {
+            Some(ref c) if c.password() == password => {
+                self.lockouts.success(email, source);
+                if c.is_suspended() {
+                    Err(AHouseError::Suspended(email.into()))
+                } else {
+                    Ok(c.clone())
+                }
+            },
+            Some(_) => {
+                self.lockouts.failure(Some(email), source);
+                Err(AHouseError::InvalidClient(email.into()))
+            },
+            None => {
+                self.lockouts.failure(None, source);
+                Err(AHouseError::InvalidClient(email.into()))
+            },
+        }
+    }
+
+    pub fn set_login_policy(&self, policy :LockoutPolicy) {
+        self.lockouts.set_policy(policy);
+    }
+
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
     }
 
     pub fn profile(&self, ctl :&str) -> Option<Client> {
-        self.clients.read().unwrap().get(ctl).cloned()
+        self.clients.read().get(ctl).cloned()
+    }
+
+    // What the client or org owes: terminated droplets plus the running time of live ones.
+    pub fn charges(&self, ctl :&str) -> f64 {
+        let now = self.now();
+        let market = self.market.read();
+        market.billed.get(ctl).cloned().unwrap_or(0.0)
+            + market.owned_by(ctl)
+                .map(|d| d.charges(now, market.pricing.schedule(d.server_type())))
+                .sum::<f64>()
+    }
+
+    pub fn drop_server(&self, ctl :&str, id :DropletId) -> bool {
+        let orgs = self.orgs.read();
+        let mut market = self.market.write();
+        match market.get(id) {
+            Some(d) if role(&orgs, ctl, d.owner()) >= Some(Role::Purchaser) => (),
+            _ => return false,
+        }
+        let (_, grant) = market.terminate(id, self.now()).unwrap();
+        drop(market);
+        drop(orgs);
+        self.unlease(id);
+        self.granted(grant);
+        true
+    }
+
+    pub fn power(&self, ctl :&str, id :DropletId, action :Power) -> Result<DropletState, AHouseError> {
+        let now = self.now();
+        let orgs = self.orgs.read();
+        let mut market = self.market.write();
+        let schedule = market.pricing.schedule(id.server_type()).clone();
+        let droplet = match market.get_mut(id) {
+            Some(d) if role(&orgs, ctl, d.owner()) >= Some(Role::Purchaser) => d,
+            _ => return Err(AHouseError::InvalidDroplet(id)),
+        };
+        match action {
+            Power::Start => droplet.start(now, &schedule),
+            Power::Stop => droplet.stop(now, &schedule),
+            Power::Reboot => droplet.reboot(now, &schedule),
+        }.map_err(|state| AHouseError::InvalidState(id, state))?;
+        Ok(droplet.state(now))
+    }
+
+    fn check_admin(&self, admin :&str) -> Result<(), AHouseError> {
+        match self.clients.read().get(admin) {
+            Some(c) if c.is_admin() && !c.is_suspended() => Ok(()),
+            _ => Err(AHouseError::PermissionDenied(admin.into())),
+        }
+    }
+
+    pub fn add_stock(&self, admin :&str, server_type :ServerType, amount :u32)
+        -> Result<u32, AHouseError> {
+        self.check_admin(admin)?;
+        let mut market = self.market.write();
+        let now = self.now();
+        let grants = (0..amount).flat_map(|_| market.release(server_type, now)).collect::<Vec<_>>();
+        let left = market.stock.get(&server_type).cloned().unwrap_or(0);
+        drop(market);
+        self.granted(grants);
+        Ok(left)
+    }
+
+    pub fn remove_stock(&self, admin :&str, server_type :ServerType, amount :u32)
+        -> Result<u32, AHouseError> {
+        self.check_admin(admin)?;
+        let mut market = self.market.write();
+        match market.stock.get_mut(&server_type) {
+            Some(v) if *v >= amount => {
+                *v -= amount;
+                Ok(*v)
+            },
+            _ => Err(AHouseError::OutOfStock(server_type)),
+        }
     }
 
-    pub fn drop_server(&self, ctl :&str, id :u32) -> bool { // TODO: make this transactional
-        let droplet =
-        {
-            let mut reserved = self.reserved_d.write().unwrap();
-            if !reserved.contains_key(&id) || reserved[&id].owner() != ctl {
-                return false
+    pub fn clients(&self, admin :&str)
+        -> Result<Vec<(Client, usize, Option<LockoutState>)>, AHouseError> {
+        self.check_admin(admin)?;
+        let clients = self.clients.read();
+        let market = self.market.read();
+        let mut list = clients.values()
+            .map(|c| (c.clone(), market.owned_by(c.email()).count(), self.lockouts.account(c.email())))
+            .collect::<Vec<_>>();
+        list.sort_by(|(a, _, _), (b, _, _)| a.email().cmp(b.email()));
+        Ok(list)
+    }
+
+    pub fn market_totals(&self, admin :&str) -> Result<Vec<TypeTotals>, AHouseError> {
+        self.check_admin(admin)?;
+        let market = self.market.read();
+        let mut types = market.stock.keys()
+            .chain(market.auctions.keys())
+            .chain(market.queues.keys())
+            .cloned()
+            .chain(market.reserved_d.values().chain(market.reserved_a.values()).map(|d| d.server_type()))
+            .collect::<Vec<_>>();
+        types.sort();
+        types.dedup();
+        Ok(types.into_iter().map(|st| TypeTotals {
+            server_type: st,
+            stock: market.stock.get(&st).cloned().unwrap_or(0),
+            held: market.reserved_d.values()
+                .chain(market.reserved_a.values())
+                .filter(|d| d.server_type() == st)
+                .count(),
+            auctions: market.auctions.contains_key(&st) as usize,
+            queued: market.queues.get(&st).map(|q| q.len()).unwrap_or(0),
+        }).collect())
+    }
+
+    pub fn lockouts(&self, admin :&str) -> Result<Vec<LockoutState>, AHouseError> {
+        self.check_admin(admin)?;
+        Ok(self.lockouts.list())
+    }
+
+    pub fn unlock(&self, admin :&str, key :&str) -> Result<(), AHouseError> {
+        self.check_admin(admin)?;
+        if self.lockouts.unlock(key) {
+            Ok(())
+        } else {
+            Err(AHouseError::InvalidClient(key.into()))
+        }
+    }
+
+    pub fn suspend(&self, admin :&str, email :&str, suspended :bool) -> Result<(), AHouseError> {
+        self.check_admin(admin)?;
+        if admin == email {
+            return Err(AHouseError::PermissionDenied(admin.into()))
+        }
+        let mut clients = self.clients.write();
+        match clients.get_mut(email) {
+            None => Err(AHouseError::InvalidClient(email.into())),
+            Some(c) => {
+                c.set_suspended(suspended);
+                if suspended {
+                    for q in self.market.write().queues.values_mut() {
+                        q.remove(email);
+                    }
+                }
+                Ok(())
+            },
+        }
+    }
+
+    pub fn delete_client(&self, admin :&str, email :&str) -> Result<usize, AHouseError> {
+        self.check_admin(admin)?;
+        if admin == email {
+            return Err(AHouseError::PermissionDenied(admin.into()))
+        }
+        let mut clients = self.clients.write();
+        if clients.remove(email).is_none() {
+            return Err(AHouseError::InvalidClient(email.into()))
+        }
+        // What the client holds for an org stays with the org. An org they were the
+        // last owner of passes to a purchaser, or goes with them if none is left.
+        let mut orgs = self.orgs.write();
+        let mut accounts = vec![email.to_owned()];
+        let mut promoted = Vec::new();
+        let mut dissolved = Vec::new();
+        for o in orgs.values_mut() {
+            let last_owner = o.role(email) == Some(Role::Owner) && o.owners() == 1;
+            o.remove(email);
+            if last_owner {
+                match o.promote() {
+                    Some(member) => promoted.push((o.name().to_owned(), member)),
+                    None => {
+                        accounts.push(o.name().to_owned());
+                        dissolved.extend(o.members().into_iter()
+                                         .filter(|(_, _, invited)| !invited)
+                                         .map(|(member, _, _)| (o.name().to_owned(), member)));
+                    },
+                }
+            }
+        }
+        orgs.retain(|name, _| !accounts.contains(name));
+        let mut market = self.market.write();
+        for q in market.queues.values_mut() {
+            let owners = q.iter()
+                .filter(|b| b.owner() == email || accounts.iter().any(|a| a == b.account()))
+                .map(|b| b.owner().to_owned())
+                .collect::<Vec<_>>();
+            for owner in owners {
+                q.remove(&owner);
             }
-            reserved.remove(&id).unwrap()
+        }
+        let ids = accounts.iter()
+            .flat_map(|account| market.owned_by(account).map(|d| d.id()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let grants = ids.iter()
+            .filter_map(|id| market.terminate(*id, self.now()))
+            .flat_map(|(_, grants)| grants)
+            .collect::<Vec<_>>();
+        for account in accounts.iter() {
+            market.billed.remove(account);
+            market.spent.remove(account);
+        }
+        drop(market);
+        drop(orgs);
+        drop(clients);
+        for (org, member) in promoted {
+            self.notify(&member, &format!("Now an owner of {}, its last owner was deleted", org));
+        }
+        for (org, member) in dissolved {
+            self.notify(&member, &format!("{} was dissolved, its last owner was deleted", org));
+        }
+        for id in ids.iter() {
+            self.unlease(*id);
+        }
+        self.granted(grants);
+        Ok(ids.len())
+    }
+
+    pub fn force_drop(&self, admin :&str, id :DropletId) -> Result<Droplet, AHouseError> {
+        self.check_admin(admin)?;
+        let mut market = self.market.write();
+        let (droplet, grant) = market.terminate(id, self.now()).ok_or(AHouseError::InvalidDroplet(id))?;
+        drop(market);
+        self.unlease(id);
+        self.granted(grant);
+        Ok(droplet)
+    }
+
+    pub fn cancel_auction(&self, admin :&str, server_type :ServerType) -> Result<(), AHouseError> {
+        self.check_admin(admin)?;
+        let mut market = self.market.write();
+        let auction = market.auctions
+            .remove(&server_type)
+            .ok_or(AHouseError::NoAuction(server_type))?;
+        auction.cancel();
+        let now = self.now();
+        market.closed(server_type, &auction, Outcome::Cancelled, now);
+        let grant = market.release(server_type, now);
+        drop(market);
+        for bidder in auction.bidders() {
+            self.notify(&bidder, &AuctionKind::TimedCancelled.message(server_type));
+        }
+        self.granted(grant);
+        Ok(())
+    }
+
+    pub fn settle_auctions(ah :Arc<AuctionHouse>) -> usize {
+        let types = {
+            let market = ah.market.read();
+            for auction in market.auctions.values() {
+                auction.cancel();
+            }
+            market.auctions.keys().cloned().collect::<Vec<_>>()
         };
-        self.stock.write().unwrap()
-            .entry(droplet.server_type())
-            .and_modify(|c| *c += 1)
-            .or_insert(1);
-        true
+        for server_type in types.iter() {
+            settle_auction(&ah, *server_type);
+        }
+        types.len()
     }
 
+    // Bids on the running auction for the type, starts one if there is stock, or
+    // queues the bid until a droplet of that type is released.
     pub fn auction(
         ah :Arc<AuctionHouse>,
         server_type :ServerType,
-        bid :Bid) -> Result<AuctionKind,AHouseError> {
-
-        let stock = ah.stock.write()?;
-        debug_assert!(stock.get(&server_type).is_some());
-        if stock.get(&server_type).unwrap().load() == 0 {
-            ah.queues.write()?.entry(server_type).or_insert(UniqueBidQueue::new())
-                .enqueue(bid)
-                .map(AuctionKind::from) //TODO
-                .map_err(AHouseError::QueueInterrupted)
-        } else {
-            let auctions = ah.auctions.write()?;
-            match auctions.get_mut(&server_type) {
-                None => {
-                    stock.get(&server_type).fetch_sub(1);
-                    let ah_arc = Arc::clone(&ah);
-                    auctions.insert(
-                        server_type,
-                        Auction::new(st, bid, |b| ah_arc.buy_auctioned(server_type, b))
-                        );
-                    AuctionKind::TimedStarted
-                },
-                Some(a) => { a.bid(bid); AuctionKind::TimedRebided },
+        bid :Bid) -> Result<AuctionKind, AHouseError> {
+
+        if bid.value() <= 0 {
+            return Err(AHouseError::InvalidBid(bid.value()))
+        }
+        let clients = ah.clients.read();
+        match clients.get(bid.owner()) {
+            None => return Err(AHouseError::InvalidClient(bid.owner().into())),
+            Some(c) if c.is_suspended() => return Err(AHouseError::Suspended(bid.owner().into())),
+            Some(_) => (),
+        };
+        let orgs = ah.orgs.read();
+        if let Some(org) = bid.org() {
+            check_role(&orgs, bid.owner(), org, Role::Purchaser)?;
+        }
+        let mut market = ah.market.write();
+        // A bid that starts or joins an auction may win it; a queued one may not.
+        let timed = market.auctions.contains_key(&server_type)
+            || market.stock.get(&server_type).is_some_and(|n| *n > 0);
+        market.check_quota(bid.account(), server_type, bid.value(), timed, ah.now())?;
+        if let Some(auction) = market.auctions.get(&server_type) {
+            let outbid = auction.bid(bid.clone())?;
+            drop(market);
+            drop(orgs);
+            drop(clients);
+            if outbid.owner() != bid.owner() {
+                ah.notify(outbid.owner(), &AuctionKind::TimedOutbid(bid.value()).message(server_type));
             }
+            return Ok(AuctionKind::TimedRebided(bid.value()))
+        }
+        if market.take(server_type).is_ok() {
+            let delay = ah.auction_duration.load(Ordering::Relaxed);
+            let auction = new_auction(&ah, server_type, bid, delay);
+            market.auctions.insert(server_type, auction);
+            Ok(AuctionKind::TimedStarted(delay))
+        } else {
+            let queue = market.queues.entry(server_type).or_default();
+            let replaced = queue.enqueue(bid.clone());
+            let position = queue.position(bid.owner()).unwrap_or(queue.len());
+            Ok(if replaced {
+                AuctionKind::QueueDroppped(position)
+            } else {
+                AuctionKind::Queued(position)
+            })
         }
     }
 }
 
-fn buy_auctioned(
-    ah :Arc<AuctionHouse>,
-    server_type :ServerType,
-    bid :Bid) -> Result<(), AHouseError> {
-
-    match ah.stock.write()?.get_mut(&server_type) {
-        None => unreachable!(),
-        Some(0) => return Err(AHouseError::OutOfStock(server_type)),
-        Some(i) => {
-            *i -= 1;
-            let droplet = Droplet::new_auctioned(server_type, bid.owner(), bid.value());
-            ah.reserved_a.write()?.insert(droplet.id(), droplet);
-            Ok(())
+fn new_auction(ah :&AuctionHouse, server_type :ServerType, bid :Bid, delay :usize) -> Auction {
+    let this = ah.this.clone();
+    Auction::new(&ah.clock, bid, delay, move |_| {
+        if let Some(ah) = this.upgrade() { settle_auction(&ah, server_type) }
+    })
+}
+
+// The droplet was taken from stock when the auction started, so the winner gets it
+// without touching stock again. If the winner is gone, or it would put them over a
+// quota they have reached since bidding, it is released instead.
+fn settle_auction(ah :&AuctionHouse, server_type :ServerType) {
+    let clients = ah.clients.read();
+    let mut market = ah.market.write();
+    let auction = match market.auctions.remove(&server_type) {
+        Some(auction) => auction,
+        None => return,
+    };
+    let bid = auction.top_bid();
+    let now = ah.now();
+    let expires = bid.lease().map(|lease| now + lease);
+    let winner = match clients.get(bid.owner()) {
+        Some(c) if !c.is_suspended() => market.check_quota(bid.account(), server_type, bid.value(), true, now),
+        _ => Err(AHouseError::InvalidClient(bid.owner().into())),
+    };
+    let (id, grants) = match winner {
+        Ok(()) => {
+            let mut droplet = Droplet::new(server_type, bid.account(), bid.value(), now);
+            droplet.set_expires(expires);
+            let id = droplet.id();
+            market.reserved_a.insert(id, droplet);
+            market.pricing.cleared(server_type, bid.value());
+            market.closed(server_type, &auction, Outcome::Sold, now);
+            (Some(id), Vec::new())
+        },
+        Err(_) => {
+            market.closed(server_type, &auction, Outcome::Unsold, now);
+            (None, market.release(server_type, now))
         },
+    };
+    drop(market);
+    drop(clients);
+    if let (Some(id), Some(expires)) = (id, expires) {
+        ah.lease(id, expires);
+    }
+    let outcome = match (id, &winner) {
+        (Some(id), _) => format!("droplet {}", id),
+        (None, Err(AHouseError::QuotaExceeded(_))) => "winner over quota, released".into(),
+        (None, _) => "winner gone, released".into(),
+    };
+    ah.audit.record(
+        None,
+        Some(bid.owner()),
+        "auction-settle",
+        &[&format!("{:?}", server_type), &bid.value().to_string()],
+        &outcome);
+    for bidder in auction.bidders() {
+        let kind = match (id, &winner) {
+            (Some(id), _) if bidder == bid.owner() => AuctionKind::TimedWon(id),
+            (None, Err(AHouseError::QuotaExceeded(_))) if bidder == bid.owner() => AuctionKind::TimedOverQuota,
+            _ => AuctionKind::TimedLost(bid.value()),
+        };
+        ah.notify(&bidder, &kind.message(server_type));
+    }
+    ah.granted(grants);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    use chrono::TimeZone;
+
+    use std::sync::Mutex;
+
+    // A market at noon with clients a@x, b@x and c@x, and the notices sent to them.
+    fn house() -> (Arc<ManualClock>, Arc<AuctionHouse>, Arc<Mutex<Vec<String>>>) {
+        let clock = Arc::new(ManualClock::new(Local.with_ymd_and_hms(2020, 1, 1, 12, 0, 0).unwrap()));
+        let ah = AuctionHouse::new(clock.clone());
+        let notices = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&notices);
+        ah.set_notifier(move |user, msg| sink.lock().unwrap().push(format!("{}: {}", user, msg)));
+        for email in &["a@x", "b@x", "c@x"] {
+            ah.register(email, "pw").unwrap();
+        }
+        (clock, ah, notices)
+    }
+
+    #[test]
+    fn auction_lifecycle() {
+        let (clock, ah, notices) = house();
+        ah.add(ServerType::Fast);
+
+        let started = AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new("a@x", 10));
+        assert!(matches!(started, Ok(AuctionKind::TimedStarted(AUCTION_DURATION))));
+        assert_eq!(ah.ls(), vec![(ServerType::Fast, 0, 80)]);
+
+        clock.advance(Duration::from_secs(30));
+        let rebid = AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new("b@x", 20));
+        assert!(matches!(rebid, Ok(AuctionKind::TimedRebided(20))));
+        let low = AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new("a@x", 15));
+        assert!(matches!(low, Err(AHouseError::BidTooLow(20))));
+
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(ah.market.read().auctions[&ServerType::Fast].time_left(), 1);
+        let queued = AuctionHouse::auction(Arc::clone(&ah), ServerType::Slow, Bid::new("c@x", 5));
+        assert!(matches!(queued, Ok(AuctionKind::Queued(1))));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(ah.market.read().auctions.is_empty());
+        let won = ah.ls_m("b@x");
+        assert_eq!(won.len(), 1);
+        assert!(ah.ls_m("a@x").is_empty());
+        let id = won[0].id();
+        assert_eq!(won[0].state(ah.now()), DropletState::Provisioning);
+
+        clock.advance(Duration::from_secs(droplet::PROVISION_SECS as u64));
+        assert_eq!(ah.ls_m("b@x")[0].state(ah.now()), DropletState::Running);
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(ah.charges("b@x"), 20.0);
+
+        // Dropping the Fast droplet puts it back in stock; the Slow bid keeps waiting.
+        assert!(ah.drop_server("b@x", id));
+        assert_eq!(ah.charges("b@x"), 20.0);
+        // The next Fast lists halfway between its base price of 40 and the 20 it cleared at.
+        assert_eq!(ah.ls(), vec![(ServerType::Fast, 1, 30)]);
+        assert_eq!(*notices.lock().unwrap(), vec![
+            "a@x: Outbid on the Fast auction, top bid is now 20".to_owned(),
+            "a@x: Lost the Fast auction, winning bid was 20".to_owned(),
+            format!("b@x: Won the Fast auction, droplet {}", id),
+        ]);
+    }
+
+    #[test]
+    fn auctions_do_not_keep_the_house_alive() {
+        let (_, ah, _) = house();
+        ah.add(ServerType::Fast);
+        AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new("a@x", 10)).unwrap();
+        let this = Arc::downgrade(&ah);
+        drop(ah);
+        assert!(this.upgrade().is_none());
+    }
+
+    #[test]
+    fn bids_must_be_positive() {
+        let (_, ah, _) = house();
+        ah.add(ServerType::Fast);
+
+        let negative = AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new("a@x", -5));
+        assert!(matches!(negative, Err(AHouseError::InvalidBid(-5))));
+        let zero = AuctionHouse::auction(Arc::clone(&ah), ServerType::Slow, Bid::new("a@x", 0));
+        assert!(matches!(zero, Err(AHouseError::InvalidBid(0))));
+        assert!(ah.market.read().auctions.is_empty());
+        assert!(ah.market.read().queues.is_empty());
+        assert_eq!(ah.ls(), vec![(ServerType::Fast, 1, 40)]);
+    }
+
+    #[test]
+    fn prices_follow_demand() {
+        let (clock, ah, _) = house();
+        for _ in 0..4 { ah.add(ServerType::Slow); }
+        assert_eq!(ah.ls(), vec![(ServerType::Slow, 4, 20)]);
+
+        let (_, first) = AuctionHouse::buy(Arc::clone(&ah), ServerType::Slow, "a@x", None, None).unwrap();
+        assert_eq!(first, 20);
+        assert_eq!(ah.ls(), vec![(ServerType::Slow, 3, 25)]);
+        let (_, second) = AuctionHouse::buy(Arc::clone(&ah), ServerType::Slow, "a@x", None, None).unwrap();
+        assert_eq!(second, 25);
+
+        let mut policy = PricingPolicy::default();
+        policy.bounds.insert(ServerType::Slow, (10, 28));
+        ah.set_pricing(policy);
+        assert_eq!(ah.ls(), vec![(ServerType::Slow, 2, 28)]);
+
+        // Each droplet is billed at the price it was bought at.
+        clock.advance(Duration::from_secs(droplet::PROVISION_SECS as u64 + 3600));
+        assert_eq!(ah.charges("a@x"), 45.0);
+    }
+
+    #[test]
+    fn scheduled_prices() {
+        let clock = Arc::new(ManualClock::new(Local.with_ymd_and_hms(2020, 1, 1, 20, 59, 55).unwrap()));
+        let ah = AuctionHouse::new(clock.clone());
+        let mut policy = PricingPolicy::default();
+        policy.schedules.insert(ServerType::Slow, pricing::Schedule {
+            periods: vec![pricing::Period::parse("* 22:00-06:00 50").unwrap()],
+        });
+        ah.set_pricing(policy);
+        ah.add(ServerType::Slow);
+        ah.register("a@x", "pw").unwrap();
+
+        let at = |d, h| Local.with_ymd_and_hms(2020, 1, d, h, 0, 0).unwrap();
+        assert_eq!(ah.prices()[..3], [
+            (ServerType::Slow, clock.now(), 100, 20),
+            (ServerType::Slow, at(1, 22), 50, 10),
+            (ServerType::Slow, at(2, 6), 100, 20),
+        ]);
+        let (id, _) = AuctionHouse::buy(Arc::clone(&ah), ServerType::Slow, "a@x", None, None).unwrap();
+
+        // Running from 21:00, an hour at the full rate and an hour at half of it.
+        clock.advance(Duration::from_secs(5 + 2 * 3600));
+        assert_eq!(ah.charges("a@x"), 30.0);
+        ah.power("a@x", id, Power::Stop).unwrap();
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(ah.charges("a@x"), 30.0);
+        assert_eq!(ah.ls(), vec![(ServerType::Slow, 0, 20)]);
+    }
+
+    #[test]
+    fn auction_history() {
+        let (clock, ah, _) = house();
+        let start = ah.now();
+        ah.add(ServerType::Fast);
+        ah.register_admin("root@x", "pw").unwrap();
+        let bid = |who, value| AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new(who, value)).unwrap();
+        let minute = Duration::from_secs(60);
+
+        bid("a@x", 10);
+        bid("b@x", 30);
+        clock.advance(minute);
+        let won = ah.ls_m("b@x")[0].id();
+        assert!(ah.drop_server("b@x", won));
+        bid("a@x", 20);
+        clock.advance(minute);
+        let won = ah.ls_m("a@x")[0].id();
+        assert!(ah.drop_server("a@x", won));
+        bid("b@x", 5);
+        ah.cancel_auction("root@x", ServerType::Fast).unwrap();
+
+        let records = ah.history(ServerType::Fast, start);
+        assert_eq!(records.iter().map(|r| (r.outcome, r.bidder.as_str(), r.price, r.bids)).collect::<Vec<_>>(), vec![
+            (Outcome::Cancelled, "b@x", 5, 1),
+            (Outcome::Sold, "a@x", 20, 1),
+            (Outcome::Sold, "b@x", 30, 2),
+        ]);
+        assert_eq!(records[2].started, start);
+        assert_eq!(records[2].closed, start + chrono::Duration::seconds(60));
+        assert_eq!(history::PriceStats::of(&records), Some(history::PriceStats {
+            min: 20,
+            max: 30,
+            mean: 25.0,
+            median: 25.0,
+        }));
+        assert_eq!(ah.history(ServerType::Fast, start + chrono::Duration::seconds(90)).len(), 2);
+    }
+
+    #[test]
+    fn leases_expire() {
+        let (clock, ah, notices) = house();
+        ah.set_lease_warning(60);
+        ah.add(ServerType::Slow);
+        let minutes = |n :u64| Duration::from_secs(n * 60);
+
+        let (id, _) = AuctionHouse::buy(Arc::clone(&ah), ServerType::Slow, "a@x", None, Some(chrono::Duration::hours(1))).unwrap();
+        let queued = Bid::new("b@x", 5).with_lease(Some(chrono::Duration::minutes(10)));
+        AuctionHouse::auction(Arc::clone(&ah), ServerType::Slow, queued).unwrap();
+        clock.advance(minutes(59));
+        assert!(matches!(ah.renew("b@x", id, chrono::Duration::minutes(30)), Err(AHouseError::InvalidDroplet(_))));
+        assert_eq!(ah.renew("a@x", id, chrono::Duration::minutes(30)).unwrap(), Local.with_ymd_and_hms(2020, 1, 1, 13, 30, 0).unwrap());
+
+        // Renewing cancelled the timers set for the first hour.
+        assert_eq!(ah.leases.lock().unwrap()[&id].len(), 2);
+        clock.advance(minutes(2));
+        assert_eq!(ah.ls_m("a@x").len(), 1);
+        clock.advance(minutes(29));
+        assert!(ah.ls_m("a@x").is_empty());
+
+        // The droplet went to the queued bid, on that bid's own lease.
+        let granted = ah.ls_m("b@x");
+        assert_eq!(granted[0].expires(), Some(Local.with_ymd_and_hms(2020, 1, 1, 13, 40, 0).unwrap()));
+        clock.advance(minutes(10));
+        assert!(ah.ls_m("b@x").is_empty());
+        assert_eq!(ah.ls(), vec![(ServerType::Slow, 1, 20)]);
+        let gid = granted[0].id();
+        assert_eq!(*notices.lock().unwrap(), vec![
+            format!("a@x: Lease ending in 60s, renew to keep droplet {}", id),
+            format!("a@x: Lease ending in 60s, renew to keep droplet {}", id),
+            format!("a@x: Lease ended, released droplet {}", id),
+            format!("b@x: Queued Slow bid granted, droplet {}", gid),
+            format!("b@x: Lease ending in 60s, renew to keep droplet {}", gid),
+            format!("b@x: Lease ended, released droplet {}", gid),
+        ]);
+
+        // Dropping a leased droplet cancels its timers too.
+        let (id, _) = AuctionHouse::buy(Arc::clone(&ah), ServerType::Slow, "a@x", None, Some(chrono::Duration::days(100000))).unwrap();
+        assert!(ah.drop_server("a@x", id));
+        assert!(ah.leases.lock().unwrap().is_empty());
+        assert!(format!("{:?}", clock).ends_with("timers: 0 }"));
+    }
+
+    #[test]
+    fn quotas() {
+        let (clock, ah, _) = house();
+        for _ in 0..4 { ah.add(ServerType::Fast); }
+        for _ in 0..2 { ah.add(ServerType::Slow); }
+        let day = chrono::Duration::hours(24);
+        let mut policy = QuotaPolicy::default();
+        policy.droplets.insert(ServerType::Fast, 2);
+        policy.wins = Some((1, day));
+        ah.set_quotas(policy);
+        let buy = |who, st| AuctionHouse::buy(Arc::clone(&ah), st, who, None, None);
+
+        buy("a@x", ServerType::Fast).unwrap();
+        buy("a@x", ServerType::Fast).unwrap();
+        assert!(matches!(buy("a@x", ServerType::Fast), Err(AHouseError::QuotaExceeded(Quota::Droplets(ServerType::Fast, 2)))));
+        buy("a@x", ServerType::Slow).unwrap();
+        assert_eq!(ah.quota("a@x", None).unwrap(), vec![
+            (Quota::Droplets(ServerType::Fast, 2), 2.0, 0.0),
+            (Quota::Wins(1, day), 0.0, 1.0),
+        ]);
+
+        let bid = |who, value| AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new(who, value));
+        bid("b@x", 10).unwrap();
+        clock.advance(Duration::from_secs(AUCTION_DURATION as u64));
+        assert_eq!(ah.ls_m("b@x").len(), 1);
+        assert!(matches!(bid("b@x", 10), Err(AHouseError::QuotaExceeded(Quota::Wins(1, _)))));
+        // A queued bid can not win an auction, so it is let through.
+        buy("c@x", ServerType::Fast).unwrap();
+        assert!(matches!(bid("b@x", 10), Ok(AuctionKind::Queued(1))));
+        clock.advance(Duration::from_secs(24 * 3600 + 1));
+        assert_eq!(ah.quota("b@x", None).unwrap()[1], (Quota::Wins(1, day), 0.0, 1.0));
+    }
+
+    #[test]
+    fn spend_quota() {
+        let (clock, ah, _) = house();
+        for _ in 0..2 { ah.add(ServerType::Slow); }
+        let day = chrono::Duration::hours(24);
+        ah.set_quotas(QuotaPolicy { spend: Some((100.0, day)), ..QuotaPolicy::default() });
+        let buy = || AuctionHouse::buy(Arc::clone(&ah), ServerType::Slow, "a@x", None, None);
+        let hours = |n :u64| Duration::from_secs(n * 3600);
+
+        // Dropped droplets still count what they ran up.
+        let (id, _) = buy().unwrap();
+        clock.advance(Duration::from_secs(droplet::PROVISION_SECS as u64) + hours(3));
+        assert!(ah.drop_server("a@x", id));
+        let (id, _) = buy().unwrap();
+        clock.advance(Duration::from_secs(droplet::PROVISION_SECS as u64) + hours(1));
+        assert_eq!(ah.quota("a@x", None).unwrap(), vec![(Quota::Spend(100.0, day), 80.0, 20.0)]);
+        // The next Slow lists at 25 an hour, which leaves no room for its first hour.
+        assert!(matches!(buy(), Err(AHouseError::QuotaExceeded(Quota::Spend(_, _)))));
+
+        // A day on, only the live droplet's last day counts.
+        clock.advance(day.to_std().unwrap());
+        assert_eq!(ah.quota("a@x", None).unwrap()[0].1, 480.0);
+        assert!(ah.drop_server("a@x", id));
+        clock.advance(day.to_std().unwrap());
+        assert_eq!(ah.quota("a@x", None).unwrap()[0].1, 0.0);
+        buy().unwrap();
+    }
+
+    #[test]
+    fn quotas_at_hand_out() {
+        let day = chrono::Duration::hours(24);
+        let close = Duration::from_secs(AUCTION_DURATION as u64);
+
+        // Bought from stock while the auction ran, the droplets it would win are over the quota.
+        let (clock, ah, notices) = house();
+        for _ in 0..3 { ah.add(ServerType::Fast); }
+        let mut policy = QuotaPolicy::default();
+        policy.droplets.insert(ServerType::Fast, 2);
+        ah.set_quotas(policy);
+        AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new("a@x", 10)).unwrap();
+        AuctionHouse::buy(Arc::clone(&ah), ServerType::Fast, "a@x", None, None).unwrap();
+        AuctionHouse::buy(Arc::clone(&ah), ServerType::Fast, "a@x", None, None).unwrap();
+        clock.advance(close);
+        assert_eq!(ah.ls_m("a@x").len(), 2);
+        assert_eq!(ah.ls()[0].1, 1);
+        assert_eq!(ah.history(ServerType::Fast, ah.now())[0].outcome, Outcome::Unsold);
+        assert_eq!(*notices.lock().unwrap(), vec!["a@x: Won the Fast auction over quota, droplet released".to_owned()]);
+
+        // Both auctions were started under the wins quota, only the first to close is won.
+        let (clock, ah, _) = house();
+        ah.add(ServerType::Fast);
+        ah.add(ServerType::Slow);
+        ah.set_quotas(QuotaPolicy { wins: Some((1, day)), ..QuotaPolicy::default() });
+        AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new("b@x", 10)).unwrap();
+        AuctionHouse::auction(Arc::clone(&ah), ServerType::Slow, Bid::new("b@x", 10)).unwrap();
+        clock.advance(close);
+        assert_eq!(ah.ls_m("b@x").iter().map(|d| d.server_type()).collect::<Vec<_>>(), vec![ServerType::Fast]);
+        let mut stock = ah.ls().iter().map(|l| (l.0, l.1)).collect::<Vec<_>>();
+        stock.sort();
+        assert_eq!(stock, vec![(ServerType::Slow, 1), (ServerType::Fast, 0)]);
+
+        // The queued bid fit the spend quota when placed, not after two hours of a
+        // Fast; the next bid in the queue gets the droplet instead.
+        let (clock, ah, notices) = house();
+        ah.add(ServerType::Fast);
+        ah.set_quotas(QuotaPolicy { spend: Some((90.0, day)), ..QuotaPolicy::default() });
+        AuctionHouse::auction(Arc::clone(&ah), ServerType::Slow, Bid::new("c@x", 20)).unwrap();
+        AuctionHouse::auction(Arc::clone(&ah), ServerType::Slow, Bid::new("a@x", 10)).unwrap();
+        AuctionHouse::buy(Arc::clone(&ah), ServerType::Fast, "c@x", None, None).unwrap();
+        clock.advance(Duration::from_secs(droplet::PROVISION_SECS as u64 + 2 * 3600));
+        ah.add(ServerType::Slow);
+        assert_eq!(ah.ls_m("c@x").len(), 1);
+        let granted = ah.ls_m("a@x");
+        assert_eq!(*notices.lock().unwrap(), vec![
+            "c@x: Queued Slow bid dropped, it is over quota".to_owned(),
+            format!("a@x: Queued Slow bid granted, droplet {}", granted[0].id()),
+        ]);
+    }
+
+    #[test]
+    fn poisoned_market_check() {
+        let now = Local.with_ymd_and_hms(2020, 1, 1, 12, 0, 0).unwrap();
+        let mut market = Market::default();
+        let held = Droplet::new(ServerType::Fast, "a@x", 40, now);
+        let misfiled = Droplet::new(ServerType::Slow, "a@x", 20, now);
+        market.reserved_d.insert(held.id(), held.clone());
+        market.reserved_a.insert(held.id(), held.clone());
+        market.reserved_a.insert(DropletId::new(ServerType::Slow), misfiled);
+
+        // The copy reserved twice is still held; the misfiled droplet goes back to stock.
+        assert_eq!(Market::check(&mut market), 2);
+        assert_eq!(market.reserved_d.keys().collect::<Vec<_>>(), vec![&held.id()]);
+        assert!(market.reserved_a.is_empty());
+        assert_eq!(market.stock, vec![(ServerType::Slow, 1)].into_iter().collect());
+    }
+
+    #[test]
+    fn organizations() {
+        let (clock, ah, notices) = house();
+        ah.add(ServerType::Slow);
+        ah.add(ServerType::Fast);
+        let buy = |who, org| AuctionHouse::buy(Arc::clone(&ah), ServerType::Slow, who, org, None);
+        let bid = |who, org| AuctionHouse::auction(Arc::clone(&ah), ServerType::Fast, Bid::new(who, 10).with_org(org));
+
+        ah.create_org("a@x", "team").unwrap();
+        assert!(matches!(ah.create_org("b@x", "team"), Err(AHouseError::OrgTaken(_))));
+        assert!(matches!(ah.register("team", "pw"), Err(AHouseError::EmailTaken(_))));
+        assert!(matches!(buy("b@x", Some("team")), Err(AHouseError::PermissionDenied(_))));
+        ah.invite("a@x", "team", "b@x", Role::Purchaser).unwrap();
+        ah.invite("a@x", "team", "c@x", Role::Viewer).unwrap();
+        assert!(matches!(ah.invite("b@x", "team", "c@x", Role::Owner), Err(AHouseError::PermissionDenied(_))));
+        assert_eq!(ah.join_org("b@x", "team").unwrap(), Role::Purchaser);
+        assert_eq!(ah.join_org("c@x", "team").unwrap(), Role::Viewer);
+        assert!(matches!(ah.join_org("c@x", "team"), Err(AHouseError::NoInvite(_))));
+
+        // Viewers see what the org holds; purchasers also buy and manage it.
+        let (id, _) = buy("b@x", Some("team")).unwrap();
+        assert!(matches!(bid("c@x", Some("team")), Err(AHouseError::PermissionDenied(_))));
+        bid("b@x", Some("team")).unwrap();
+        clock.advance(Duration::from_secs(AUCTION_DURATION as u64));
+        let held = ah.ls_m("c@x");
+        assert_eq!(held.iter().map(|d| d.owner()).collect::<Vec<_>>(), vec!["team", "team"]);
+        assert!(!ah.drop_server("c@x", id));
+        assert_eq!(ah.history(ServerType::Fast, ah.now())[0].bidder, "team");
+
+        // The droplets stay with the org when the purchaser leaves.
+        ah.remove_member("b@x", "team", "b@x").unwrap();
+        assert!(ah.ls_m("b@x").is_empty());
+        assert!(matches!(ah.remove_member("a@x", "team", "a@x"), Err(AHouseError::LastOwner(_))));
+        assert!(matches!(ah.set_role("a@x", "team", "b@x", Role::Owner), Err(AHouseError::NotMember(_, _))));
+        assert!(ah.drop_server("a@x", id));
+        let summary = ah.org("c@x", "team").unwrap();
+        assert_eq!(summary.members, vec![("a@x".to_owned(), Role::Owner, false), ("c@x".to_owned(), Role::Viewer, false)]);
+        assert_eq!(summary.droplets, 1);
+        assert_eq!(ah.orgs("c@x"), vec![("team".to_owned(), Role::Viewer, false)]);
+        assert_eq!(notices.lock().unwrap()[..2], [
+            "b@x: Invited to team as purchaser, org-join team to accept".to_owned(),
+            "c@x: Invited to team as viewer, org-join team to accept".to_owned(),
+        ]);
+
+        // Deleting the last owner hands the org to a purchaser; with only viewers left it ends.
+        ah.register_admin("root@x", "pw").unwrap();
+        let held = |ah :&AuctionHouse| ah.market_totals("root@x").unwrap()
+            .iter()
+            .map(|t| (t.server_type, t.stock, t.held))
+            .collect::<Vec<_>>();
+        assert_eq!(held(&ah), vec![(ServerType::Slow, 1, 0), (ServerType::Fast, 0, 1)]);
+        ah.invite("a@x", "team", "b@x", Role::Purchaser).unwrap();
+        ah.join_org("b@x", "team").unwrap();
+        assert_eq!(ah.delete_client("root@x", "a@x").unwrap(), 0);
+        assert_eq!(ah.orgs("b@x"), vec![("team".to_owned(), Role::Owner, false)]);
+        assert_eq!(ah.orgs("c@x"), vec![("team".to_owned(), Role::Viewer, false)]);
+        assert_eq!(notices.lock().unwrap().last().unwrap(), "b@x: Now an owner of team, its last owner was deleted");
+        assert_eq!(ah.delete_client("root@x", "b@x").unwrap(), 1);
+        assert!(matches!(ah.org("c@x", "team"), Err(AHouseError::NoOrg(_))));
+        assert!(ah.orgs("c@x").is_empty());
+        assert_eq!(notices.lock().unwrap().last().unwrap(), "c@x: team was dissolved, its last owner was deleted");
+        assert_eq!(held(&ah), vec![(ServerType::Slow, 1, 0), (ServerType::Fast, 1, 0)]);
     }
 }
@@ -1,20 +1,24 @@
-use super::server_type::ServerType;
 use super::bid::Bid;
+use crate::clock::Clock;
 use crate::task::Task;
 
+use chrono::{DateTime, Local};
+
 use std::collections::BinaryHeap;
 use std::sync::{RwLock, Arc};
 
 #[derive(Debug)]
 pub struct Auction {
-    server_type :ServerType,
     bids :Arc<RwLock<BinaryHeap<Bid>>>,
-    callback :Task
+    callback :Task,
+    started :DateTime<Local>,
+    // Bids placed before a restore, when only the top one was kept.
+    earlier :usize,
 }
 
 pub enum BidError {
     BidTooLow(i32),
-    LockError,
+    LockError(String),
 }
 
 impl<T> From<std::sync::PoisonError<T>> for BidError {
@@ -24,9 +28,9 @@ impl<T> From<std::sync::PoisonError<T>> for BidError {
 }
 
 impl Auction {
-    pub fn new<T>(server_type :ServerType, bid :Bid, f :T) -> Auction
+    pub fn new<T>(clock :&Arc<dyn Clock>, bid :Bid, delay :usize, f :T) -> Auction
         where
-        T: FnOnce(Bid) -> (),
+        T: FnOnce(Bid),
         T: std::marker::Send + 'static
         {
             let bids = Arc::new(RwLock::new({
@@ -36,22 +40,63 @@ impl Auction {
             }));
             let bids_arc = Arc::clone(&bids);
             Auction {
-                server_type,
-                bids: bids,
-                callback: Task::new(|| f(Auction::highest_bid(bids_arc)), 10)
+                bids,
+                callback: Task::new(clock, || f(Auction::highest_bid(bids_arc)), delay),
+                started: clock.now(),
+                earlier: 0,
             }
         }
 
-    pub fn bid(&mut self, bid :Bid) -> Result<(), BidError> {
-        let top_bid = self.bids.read()?.peek().unwrap();
-        if top_bid > &bid {
+    // An auction carried over from a snapshot keeps its start and bid count.
+    pub fn resumed(mut self, started :DateTime<Local>, bids :usize) -> Self {
+        self.started = started;
+        self.earlier = bids.saturating_sub(1);
+        self
+    }
+
+    // Returns the bid that was on top before this one.
+    pub fn bid(&self, bid :Bid) -> Result<Bid, BidError> {
+        let mut bids = self.bids.write()?;
+        let top_bid = bids.peek().unwrap().clone();
+        if top_bid >= bid {
             Err(BidError::BidTooLow(top_bid.value()))
         } else {
-            self.bids.write()?.push(bid);
-            Ok(())
+            bids.push(bid);
+            Ok(top_bid)
         }
     }
 
+    pub fn top_bid(&self) -> Bid {
+        Auction::highest_bid(Arc::clone(&self.bids))
+    }
+
+    // Everyone who bid, each once.
+    pub fn bidders(&self) -> Vec<String> {
+        let mut bidders = self.bids.read().unwrap()
+            .iter()
+            .map(|b| b.owner().to_owned())
+            .collect::<Vec<_>>();
+        bidders.sort();
+        bidders.dedup();
+        bidders
+    }
+
+    pub fn started(&self) -> DateTime<Local> {
+        self.started
+    }
+
+    pub fn bid_count(&self) -> usize {
+        self.earlier + self.bids.read().unwrap().len()
+    }
+
+    pub fn time_left(&self) -> usize {
+        self.callback.delay()
+    }
+
+    pub fn cancel(&self) {
+        self.callback.cancel();
+    }
+
     fn highest_bid(bids :Arc<RwLock<BinaryHeap<Bid>>>) -> Bid {
         bids.read().unwrap().peek().unwrap().clone()
     }
@@ -0,0 +1,132 @@
+use chrono::Local;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug)]
+struct AuditFile {
+    path :PathBuf,
+    file :File,
+    size :u64,
+    max_bytes :u64,
+    keep :usize,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditLog(Mutex<Option<AuditFile>>);
+
+fn field(s :&str) -> String {
+    s.replace(['\t', '\n', '\r'], " ")
+}
+
+impl AuditFile {
+    fn open(path :&Path, max_bytes :u64, keep :usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditFile { path: path.to_owned(), file, size, max_bytes, keep })
+    }
+
+    fn rotated(&self, n :usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            return Ok(())
+        }
+        let _ = fs::remove_file(self.rotated(self.keep));
+        for n in (1..self.keep).rev() {
+            let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        *self = AuditFile::open(&self.path, self.max_bytes, self.keep)?;
+        Ok(())
+    }
+
+    fn write(&mut self, line :&str) -> io::Result<()> {
+        if self.max_bytes > 0 && self.size + line.len() as u64 > self.max_bytes && self.size > 0 {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+impl AuditLog {
+    pub fn open(&self, path :&Path, max_bytes :u64, keep :usize) -> io::Result<()> {
+        *self.0.lock().unwrap() = Some(AuditFile::open(path, max_bytes, keep)?);
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    pub fn record(
+        &self,
+        session :Option<usize>,
+        account :Option<&str>,
+        action :&str,
+        params :&[&str],
+        outcome :&str) {
+
+        let mut file = self.0.lock().unwrap();
+        let file = match file.as_mut() {
+            None => return,
+            Some(file) => file,
+        };
+        let line = format!("{}\t{}\t{}\t{}\t{}\t{}\n",
+                           Local::now().to_rfc3339(),
+                           session.map(|s| s.to_string()).unwrap_or_else(|| "-".into()),
+                           field(account.unwrap_or("-")),
+                           field(action),
+                           field(&params.join(" ")),
+                           field(outcome));
+        if let Err(e) = file.write(&line) {
+            eprintln!("audit: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join(format!("sd-rust-audit-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.log");
+        let log = AuditLog::default();
+        // Every record after the first rotates, and two rotated files are kept.
+        log.open(&path, 1, 2).unwrap();
+        for action in ["a", "b", "c", "d"] {
+            log.record(Some(1), Some("a@x"), action, &["Fast", "10"], "ok\tdone");
+        }
+        // Nothing is written once it is closed.
+        log.close();
+        log.record(None, None, "e", &[], "");
+
+        let action = |p :&Path| fs::read_to_string(p).unwrap()
+            .lines()
+            .map(|l| l.split('\t').skip(3).collect::<Vec<_>>().join("|"))
+            .collect::<Vec<_>>();
+        assert_eq!(action(&path), vec!["d|Fast 10|ok done"]);
+        assert_eq!(action(&dir.join("audit.log.1")), vec!["c|Fast 10|ok done"]);
+        assert_eq!(action(&dir.join("audit.log.2")), vec!["b|Fast 10|ok done"]);
+        assert!(!dir.join("audit.log.3").exists());
+
+        // Reopened, the file is appended to until it reaches the limit.
+        log.open(&path, 1 << 20, 2).unwrap();
+        log.record(None, None, "e", &[], "");
+        assert_eq!(action(&path), vec!["d|Fast 10|ok done", "e||"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
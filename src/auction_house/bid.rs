@@ -1,9 +1,15 @@
+use chrono::Duration;
+
 use std::cmp::{Ordering};
 
+// `lease` is how long the droplet is kept if the bid wins; None keeps it until dropped.
+// `owner` placed the bid, and the droplet goes to `org` if it was placed for one.
 #[derive(Debug, Clone)]
 pub struct Bid {
     value: i32,
     owner: String,
+    lease: Option<Duration>,
+    org: Option<String>,
 }
 
 impl PartialOrd for Bid {
@@ -31,9 +37,34 @@ impl Bid {
         Bid {
             value,
             owner: owner.into(),
+            lease: None,
+            org: None,
         }
     }
 
+    pub fn with_lease(mut self, lease :Option<Duration>) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    pub fn with_org(mut self, org :Option<&str>) -> Self {
+        self.org = org.map(|o| o.to_owned());
+        self
+    }
+
+    pub fn org(&self) -> Option<&str> {
+        self.org.as_deref()
+    }
+
+    // Where the droplet and its charges go.
+    pub fn account(&self) -> &str {
+        self.org.as_deref().unwrap_or(&self.owner)
+    }
+
+    pub fn lease(&self) -> Option<Duration> {
+        self.lease
+    }
+
     pub fn owner(&self) -> &str {
         &self.owner
     }
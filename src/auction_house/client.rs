@@ -2,6 +2,8 @@
 pub struct Client {
     email :String,
     password :String,
+    admin :bool,
+    suspended :bool,
 }
 
 impl Client {
@@ -9,6 +11,15 @@ impl Client {
         Client {
             email,
             password,
+            admin: false,
+            suspended: false,
+        }
+    }
+
+    pub fn new_admin(email :String, password :String) -> Self {
+        Client {
+            admin: true,
+            ..Client::new(email, password)
         }
     }
 
@@ -18,4 +29,16 @@ impl Client {
     pub fn password(&self) -> &str {
         &self.password
     }
+
+    pub fn is_admin(&self) -> bool {
+        self.admin
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    pub fn set_suspended(&mut self, suspended :bool) {
+        self.suspended = suspended;
+    }
 }
@@ -1,38 +1,138 @@
-use super::client::Client;
 use super::server_type::ServerType;
+use super::pricing::Schedule;
 
-use std::sync::atomic::{AtomicUsize, Ordering};
+use chrono::{DateTime, Duration, Local};
+use ulid::Ulid;
 
-static ID :AtomicUsize = AtomicUsize::new(0);
+use std::fmt;
 
+// How long a droplet stays in provisioning after it is created, started or rebooted.
+pub const PROVISION_SECS :i64 = 5;
+
+// Printed as the lowercase server type and a ULID, e.g. `fast-01J9ZQ3V5W8K2M4N6P7R9T1X3Y`.
+// The ULID's random part makes IDs unguessable and unique across restarts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DropletId {
+    tp :ServerType,
+    ulid :Ulid,
+}
+
+impl DropletId {
+    pub fn new(tp :ServerType) -> Self {
+        DropletId { tp, ulid: Ulid::new() }
+    }
+
+    pub fn parse(s :&str) -> Option<Self> {
+        let (prefix, ulid) = s.split_at(s.find('-')?);
+        Some(DropletId {
+            tp: ServerType::from_prefix(prefix)?,
+            ulid: Ulid::from_string(&ulid[1..]).ok()?,
+        })
+    }
+
+    pub fn server_type(&self) -> ServerType {
+        self.tp
+    }
+}
+
+impl fmt::Display for DropletId {
+    fn fmt(&self, f :&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.tp.prefix(), self.ulid)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DropletState {
+    Provisioning,
+    Running,
+    Stopped,
+    Terminated,
+}
+
+impl DropletState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DropletState::Provisioning => "provisioning",
+            DropletState::Running => "running",
+            DropletState::Stopped => "stopped",
+            DropletState::Terminated => "terminated",
+        }
+    }
+
+    pub fn parse(s :&str) -> Option<Self> {
+        match s {
+            "provisioning" => Some(DropletState::Provisioning),
+            "running" => Some(DropletState::Running),
+            "stopped" => Some(DropletState::Stopped),
+            "terminated" => Some(DropletState::Terminated),
+            _ => None,
+        }
+    }
+}
+
+// `value` is the hourly rate, scaled by the type's schedule while it accrues. A leased
+// droplet goes back to stock at `expires`. Only time spent running is billed: `billed`
+// holds the running time before `since`, the moment the current state was entered,
+// and `accrued` what that time cost.
 #[derive(Debug, Clone)]
 pub struct Droplet {
-    id :u32,
-    tp :ServerType,
+    id :DropletId,
     owner :String,
     value :i32,
+    state :DropletState,
+    created :DateTime<Local>,
+    since :DateTime<Local>,
+    billed :Duration,
+    accrued :f64,
+    expires :Option<DateTime<Local>>,
 }
 
 impl Droplet {
-    pub fn new_reserved(tp :ServerType, owner :&str) -> Self {
+    pub fn new(tp :ServerType, owner :&str, value :i32, now :DateTime<Local>) -> Self {
         Droplet {
-            tp,
-            id: ID.fetch_add(1, Ordering::SeqCst) as u32,
+            id: DropletId::new(tp),
             owner: owner.to_string(),
-            value: tp.price(),
+            value,
+            state: DropletState::Provisioning,
+            created: now,
+            since: now,
+            billed: Duration::zero(),
+            accrued: 0.0,
+            expires: None,
         }
     }
 
-    pub fn new_auctioned(tp :ServerType, owner :&str, value :i32) -> Self {
+    pub fn restore(id :DropletId, owner :&str, value :i32, now :DateTime<Local>) -> Self {
         Droplet {
-            tp,
-            id: ID.fetch_add(1, Ordering::SeqCst) as u32,
+            id,
             owner: owner.to_string(),
-            value: value,
+            value,
+            state: DropletState::Running,
+            created: now,
+            since: now,
+            billed: Duration::zero(),
+            accrued: 0.0,
+            expires: None,
         }
     }
 
-    pub fn id(&self) -> u32 {
+    pub fn with_lifecycle(
+        mut self,
+        state :DropletState,
+        created :DateTime<Local>,
+        since :DateTime<Local>,
+        billed :Duration,
+        accrued :f64) -> Self {
+
+        self.state = state;
+        self.created = created;
+        self.since = since;
+        self.billed = billed;
+        self.accrued = accrued;
+        self
+    }
+
+    pub fn id(&self) -> DropletId {
         self.id
     }
 
@@ -41,7 +141,114 @@ impl Droplet {
     }
 
     pub fn server_type(&self) -> ServerType {
-        self.tp
+        self.id.server_type()
+    }
+
+    pub fn value(&self) -> i32 {
+        self.value
+    }
+
+    pub fn created(&self) -> DateTime<Local> {
+        self.created
+    }
+
+    pub fn since(&self) -> DateTime<Local> {
+        self.since
+    }
+
+    pub fn billed(&self) -> Duration {
+        self.billed
+    }
+
+    pub fn expires(&self) -> Option<DateTime<Local>> {
+        self.expires
+    }
+
+    pub fn set_expires(&mut self, expires :Option<DateTime<Local>>) {
+        self.expires = expires;
+    }
+
+    pub fn accrued(&self) -> f64 {
+        self.accrued
+    }
+
+    pub fn stored_state(&self) -> DropletState {
+        self.state
+    }
+
+    // Provisioning finishes on its own; work out where the droplet is at `now`.
+    fn current(&self, now :DateTime<Local>) -> (DropletState, DateTime<Local>) {
+        let ready = self.since + Duration::seconds(PROVISION_SECS);
+        match self.state {
+            DropletState::Provisioning if now >= ready => (DropletState::Running, ready),
+            state => (state, self.since),
+        }
+    }
+
+    pub fn state(&self, now :DateTime<Local>) -> DropletState {
+        self.current(now).0
+    }
+
+    pub fn uptime(&self, now :DateTime<Local>) -> Option<Duration> {
+        match self.current(now) {
+            (DropletState::Running, since) => Some(now - since),
+            _ => None,
+        }
+    }
+
+    pub fn running_time(&self, now :DateTime<Local>) -> Duration {
+        self.billed + self.uptime(now).unwrap_or_else(Duration::zero)
     }
 
+    pub fn charges(&self, now :DateTime<Local>, schedule :&Schedule) -> f64 {
+        let running = match self.uptime(now) {
+            Some(uptime) => schedule.weighted_secs(now - uptime, now),
+            None => 0.0,
+        };
+        self.accrued + running * self.value as f64 / 3600.0
+    }
+
+    // What it ran up from `from` on. Only the current state's time can be split at
+    // `from`, so a droplet that changed state since counts what it ran up before too.
+    pub fn charges_since(&self, from :DateTime<Local>, now :DateTime<Local>, schedule :&Schedule) -> f64 {
+        if self.created >= from || self.since > from {
+            return self.charges(now, schedule)
+        }
+        match self.uptime(now) {
+            Some(uptime) => schedule.weighted_secs((now - uptime).max(from), now) * self.value as f64 / 3600.0,
+            None => 0.0,
+        }
+    }
+
+    fn enter(&mut self, state :DropletState, now :DateTime<Local>, schedule :&Schedule) {
+        self.accrued = self.charges(now, schedule);
+        self.billed = self.running_time(now);
+        self.state = state;
+        self.since = now;
+    }
+
+    pub fn start(&mut self, now :DateTime<Local>, schedule :&Schedule) -> Result<(), DropletState> {
+        match self.state(now) {
+            DropletState::Stopped => { self.enter(DropletState::Provisioning, now, schedule); Ok(()) },
+            state => Err(state),
+        }
+    }
+
+    pub fn stop(&mut self, now :DateTime<Local>, schedule :&Schedule) -> Result<(), DropletState> {
+        match self.state(now) {
+            DropletState::Running => { self.enter(DropletState::Stopped, now, schedule); Ok(()) },
+            state => Err(state),
+        }
+    }
+
+    pub fn reboot(&mut self, now :DateTime<Local>, schedule :&Schedule) -> Result<(), DropletState> {
+        match self.state(now) {
+            DropletState::Running => { self.enter(DropletState::Provisioning, now, schedule); Ok(()) },
+            state => Err(state),
+        }
+    }
+
+    pub fn terminate(&mut self, now :DateTime<Local>, schedule :&Schedule) {
+        self.enter(DropletState::Terminated, now, schedule);
+    }
 }
@@ -0,0 +1,117 @@
+use super::server_type::ServerType;
+
+use chrono::{DateTime, Local};
+
+use std::collections::{HashMap, VecDeque};
+
+// Closed auctions kept per server type; older ones are forgotten.
+pub const HISTORY_LEN :usize = 1000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Sold,
+    // The winner was gone by the time it closed, so the droplet went back.
+    Unsold,
+    Cancelled,
+}
+
+impl Outcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Sold => "sold",
+            Outcome::Unsold => "unsold",
+            Outcome::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(s :&str) -> Option<Self> {
+        match s {
+            "sold" => Some(Outcome::Sold),
+            "unsold" => Some(Outcome::Unsold),
+            "cancelled" => Some(Outcome::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+// `bidder` and `price` are the top bid when the auction closed; the price only
+// cleared if it was sold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuctionRecord {
+    pub server_type :ServerType,
+    pub outcome :Outcome,
+    pub bidder :String,
+    pub price :i32,
+    pub bids :usize,
+    pub started :DateTime<Local>,
+    pub closed :DateTime<Local>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceStats {
+    pub min :i32,
+    pub max :i32,
+    pub mean :f64,
+    pub median :f64,
+}
+
+impl PriceStats {
+    // Over the clearing prices of the sold auctions, if any sold.
+    pub fn of(records :&[AuctionRecord]) -> Option<Self> {
+        let mut prices = records.iter()
+            .filter(|r| r.outcome == Outcome::Sold)
+            .map(|r| r.price)
+            .collect::<Vec<_>>();
+        if prices.is_empty() {
+            return None
+        }
+        prices.sort();
+        let n = prices.len();
+        Some(PriceStats {
+            min: prices[0],
+            max: prices[n - 1],
+            mean: prices.iter().sum::<i32>() as f64 / n as f64,
+            median: (prices[(n - 1) / 2] + prices[n / 2]) as f64 / 2.0,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    records :HashMap<ServerType, VecDeque<AuctionRecord>>,
+}
+
+impl History {
+    pub fn record(&mut self, record :AuctionRecord) {
+        let records = self.records.entry(record.server_type).or_default();
+        records.push_back(record);
+        while records.len() > HISTORY_LEN { records.pop_front(); }
+    }
+
+    // Newest first.
+    pub fn since(&self, server_type :ServerType, from :DateTime<Local>) -> Vec<AuctionRecord> {
+        self.records.get(&server_type)
+            .map(|records| records.iter()
+                 .rev()
+                 .take_while(|r| r.closed >= from)
+                 .cloned()
+                 .collect())
+            .unwrap_or_default()
+    }
+
+    // Auctions of any type the bidder won at or after `from`.
+    pub fn wins(&self, bidder :&str, from :DateTime<Local>) -> usize {
+        self.records.values()
+            .map(|records| records.iter()
+                 .rev()
+                 .take_while(|r| r.closed >= from)
+                 .filter(|r| r.outcome == Outcome::Sold && r.bidder == bidder)
+                 .count())
+            .sum()
+    }
+
+    // Oldest first, as `record` expects them back.
+    pub fn iter(&self) -> impl Iterator<Item = &AuctionRecord> {
+        self.records.values().flat_map(|records| records.iter())
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LockoutPolicy {
+    pub max_failures :u32,
+    pub max_failures_per_ip :u32,
+    pub base_delay :Duration,
+    pub lockout :Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        LockoutPolicy {
+            max_failures: 5,
+            max_failures_per_ip: 20,
+            base_delay: Duration::from_secs(1),
+            lockout: Duration::from_secs(900),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Attempts {
+    failures :u32,
+    last_failure :Instant,
+}
+
+impl Attempts {
+    fn next_allowed(&self, max_failures :u32, escalate :bool, policy :&LockoutPolicy) -> Instant {
+        let wait = if self.failures >= max_failures {
+            policy.lockout
+        } else if !escalate {
+            Duration::from_secs(0)
+        } else {
+            let exp = self.failures.saturating_sub(1).min(16);
+            (policy.base_delay * 2u32.pow(exp)).min(policy.lockout)
+        };
+        self.last_failure + wait
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LockoutState {
+    pub key :String,
+    pub failures :u32,
+    pub locked :bool,
+    pub retry_in :Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Lockouts {
+    policy :RwLock<LockoutPolicy>,
+    accounts :Mutex<HashMap<String, Attempts>>,
+    addresses :Mutex<HashMap<IpAddr, Attempts>>,
+}
+
+fn wait_for<K :Eq + Hash>(
+    map :&Mutex<HashMap<K, Attempts>>,
+    key :&K,
+    max_failures :u32,
+    escalate :bool,
+    policy :&LockoutPolicy) -> Option<Duration> {
+
+    let mut map = map.lock().unwrap();
+    let next = map.get(key)?.next_allowed(max_failures, escalate, policy);
+    let now = Instant::now();
+    if next > now {
+        Some(next - now)
+    } else {
+        if map[key].failures >= max_failures {
+            map.remove(key);
+        }
+        None
+    }
+}
+
+fn record<K :Eq + Hash>(map :&Mutex<HashMap<K, Attempts>>, key :K) {
+    let mut map = map.lock().unwrap();
+    let attempts = map.entry(key).or_insert(Attempts { failures: 0, last_failure: Instant::now() });
+    attempts.failures += 1;
+    attempts.last_failure = Instant::now();
+}
+
+fn state<K :ToString>(
+    key :&K,
+    attempts :&Attempts,
+    max_failures :u32,
+    escalate :bool,
+    policy :&LockoutPolicy) -> LockoutState {
+
+    LockoutState {
+        key: key.to_string(),
+        failures: attempts.failures,
+        locked: attempts.failures >= max_failures,
+        retry_in: attempts.next_allowed(max_failures, escalate, policy)
+            .saturating_duration_since(Instant::now()),
+    }
+}
+
+impl Lockouts {
+    pub fn set_policy(&self, policy :LockoutPolicy) {
+        *self.policy.write().unwrap() = policy;
+    }
+
+    pub fn check(&self, email :&str, source :Option<IpAddr>) -> Result<(), Duration> {
+        let policy = *self.policy.read().unwrap();
+        let account = wait_for(&self.accounts, &email.to_owned(), policy.max_failures, true, &policy);
+        // Addresses can be shared by many clients, so they are locked out but never slowed down.
+        let address = source.and_then(|ip|
+            wait_for(&self.addresses, &ip, policy.max_failures_per_ip, false, &policy));
+        match account.into_iter().chain(address).max() {
+            Some(wait) => Err(wait),
+            None => Ok(()),
+        }
+    }
+
+    pub fn failure(&self, email :Option<&str>, source :Option<IpAddr>) {
+        if let Some(email) = email {
+            record(&self.accounts, email.to_owned());
+        }
+        if let Some(ip) = source {
+            record(&self.addresses, ip);
+        }
+    }
+
+    pub fn success(&self, email :&str, source :Option<IpAddr>) {
+        self.accounts.lock().unwrap().remove(email);
+        if let Some(ip) = source {
+            self.addresses.lock().unwrap().remove(&ip);
+        }
+    }
+
+    pub fn account(&self, email :&str) -> Option<LockoutState> {
+        let policy = *self.policy.read().unwrap();
+        self.accounts.lock().unwrap()
+            .get(email)
+            .map(|a| state(&email, a, policy.max_failures, true, &policy))
+    }
+
+    pub fn list(&self) -> Vec<LockoutState> {
+        let policy = *self.policy.read().unwrap();
+        let mut list = self.accounts.lock().unwrap()
+            .iter()
+            .map(|(k, a)| state(k, a, policy.max_failures, true, &policy))
+            .collect::<Vec<_>>();
+        list.extend(self.addresses.lock().unwrap()
+                    .iter()
+                    .map(|(k, a)| state(k, a, policy.max_failures_per_ip, false, &policy)));
+        list.sort_by(|a, b| a.key.cmp(&b.key));
+        list
+    }
+
+    pub fn unlock(&self, key :&str) -> bool {
+        let account = self.accounts.lock().unwrap().remove(key).is_some();
+        let address = key.parse::<IpAddr>()
+            .map(|ip| self.addresses.lock().unwrap().remove(&ip).is_some())
+            .unwrap_or(false);
+        account || address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    fn about(wait :Result<(), Duration>, secs :u64) -> bool {
+        let wait = wait.unwrap_err();
+        wait <= Duration::from_secs(secs) && wait > Duration::from_secs(secs) - Duration::from_secs(1)
+    }
+
+    #[test]
+    fn failed_logins() {
+        let lockouts = Lockouts::default();
+        lockouts.set_policy(LockoutPolicy {
+            max_failures: 4,
+            max_failures_per_ip: 6,
+            base_delay: Duration::from_secs(100),
+            lockout: Duration::from_secs(1000),
+        });
+        let ip :IpAddr = "10.0.0.1".parse().unwrap();
+
+        // Each failure doubles the wait, until the account is locked out.
+        assert!(lockouts.check("a@x", Some(ip)).is_ok());
+        for secs in [100, 200, 400, 1000] {
+            lockouts.failure(Some("a@x"), Some(ip));
+            assert!(about(lockouts.check("a@x", None), secs));
+        }
+        assert!(lockouts.account("a@x").unwrap().locked);
+        assert!(lockouts.check("b@x", None).is_ok());
+
+        // The address is never slowed down, only locked out.
+        assert!(lockouts.check("b@x", Some(ip)).is_ok());
+        lockouts.failure(Some("b@x"), Some(ip));
+        lockouts.failure(None, Some(ip));
+        assert!(about(lockouts.check("c@x", Some(ip)), 1000));
+        assert!(lockouts.check("c@x", None).is_ok());
+        assert_eq!(lockouts.list().iter().map(|s| (s.key.as_str(), s.failures, s.locked)).collect::<Vec<_>>(), vec![
+            ("10.0.0.1", 6, true),
+            ("a@x", 4, true),
+            ("b@x", 1, false),
+        ]);
+
+        // A success forgets the failures; so does an admin unlocking the key.
+        lockouts.success("b@x", Some(ip));
+        assert!(lockouts.check("b@x", Some(ip)).is_ok());
+        assert!(lockouts.unlock("a@x"));
+        assert!(!lockouts.unlock("a@x"));
+        assert!(lockouts.check("a@x", None).is_ok());
+        assert!(lockouts.list().is_empty());
+    }
+
+    #[test]
+    fn lockouts_expire() {
+        let lockouts = Lockouts::default();
+        lockouts.set_policy(LockoutPolicy {
+            max_failures: 1,
+            max_failures_per_ip: 1,
+            base_delay: Duration::from_secs(1),
+            lockout: Duration::from_millis(20),
+        });
+        lockouts.failure(Some("a@x"), None);
+        assert!(lockouts.check("a@x", None).is_err());
+        thread::sleep(Duration::from_millis(50));
+        assert!(lockouts.check("a@x", None).is_ok());
+        assert!(lockouts.account("a@x").is_none());
+    }
+}
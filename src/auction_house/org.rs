@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+// Ordered by what they allow: each role can do all the ones before it can.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    // Sees the org's droplets and charges.
+    Viewer,
+    // Also buys and bids for the org and manages its droplets.
+    Purchaser,
+    // Also invites, removes and sets the role of members.
+    Owner,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Purchaser => "purchaser",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(s :&str) -> Option<Self> {
+        match s {
+            "viewer" => Some(Role::Viewer),
+            "purchaser" => Some(Role::Purchaser),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+// An org as its members see it: who is in it, and what it holds and owes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrgSummary {
+    pub members :Vec<(String, Role, bool)>,
+    pub droplets :usize,
+    pub charges :f64,
+}
+
+// An account shared by its members. Droplets, bids and charges of an org are
+// filed under its name the way a client's are under their email, so the org
+// keeps them whoever joins or leaves.
+#[derive(Debug, Clone)]
+pub struct Org {
+    name :String,
+    members :HashMap<String, Role>,
+    invites :HashMap<String, Role>,
+}
+
+impl Org {
+    pub fn new(name :&str, owner :&str) -> Self {
+        let mut members = HashMap::new();
+        members.insert(owner.to_owned(), Role::Owner);
+        Org {
+            name: name.to_owned(),
+            members,
+            invites: HashMap::new(),
+        }
+    }
+
+    // Short enough for a table and never mistaken for an email.
+    pub fn valid_name(name :&str) -> bool {
+        !name.is_empty() && name.len() <= 32
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    // An org as read back from a snapshot, before its members are.
+    pub fn restore(name :&str) -> Self {
+        Org {
+            name: name.to_owned(),
+            members: HashMap::new(),
+            invites: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, email :&str, role :Role) {
+        self.members.insert(email.to_owned(), role);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn role(&self, email :&str) -> Option<Role> {
+        self.members.get(email).cloned()
+    }
+
+    pub fn invited(&self, email :&str) -> Option<Role> {
+        self.invites.get(email).cloned()
+    }
+
+    // Members, then pending invites, each by email.
+    pub fn members(&self) -> Vec<(String, Role, bool)> {
+        let mut members = self.members.iter().map(|(e, r)| (e.clone(), *r, false)).collect::<Vec<_>>();
+        members.sort();
+        let mut invites = self.invites.iter().map(|(e, r)| (e.clone(), *r, true)).collect::<Vec<_>>();
+        invites.sort();
+        members.extend(invites);
+        members
+    }
+
+    pub fn owners(&self) -> usize {
+        self.members.values().filter(|r| **r == Role::Owner).count()
+    }
+
+    pub fn invite(&mut self, email :&str, role :Role) {
+        self.invites.insert(email.to_owned(), role);
+    }
+
+    // Turns the invite into a membership.
+    pub fn join(&mut self, email :&str) -> Option<Role> {
+        let role = self.invites.remove(email)?;
+        self.members.insert(email.to_owned(), role);
+        Some(role)
+    }
+
+    pub fn set_role(&mut self, email :&str, role :Role) -> bool {
+        match self.members.get_mut(email) {
+            Some(r) => { *r = role; true },
+            None => false,
+        }
+    }
+
+    // Makes the first purchaser by email an owner when the last one leaves.
+    // Viewers are never promoted. Returns who, if there is a purchaser.
+    pub fn promote(&mut self) -> Option<String> {
+        let email = self.members.iter()
+            .filter(|(_, r)| **r == Role::Purchaser)
+            .map(|(e, _)| e.clone())
+            .min()?;
+        self.members.insert(email.clone(), Role::Owner);
+        Some(email)
+    }
+
+    // Drops a member or a pending invite.
+    pub fn remove(&mut self, email :&str) -> bool {
+        self.members.remove(email).is_some() || self.invites.remove(email).is_some()
+    }
+}
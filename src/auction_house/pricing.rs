@@ -0,0 +1,193 @@
+use super::server_type::ServerType;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Timelike};
+
+use std::collections::{HashMap, VecDeque};
+
+const DAYS :[&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+static FLAT :Schedule = Schedule { periods: Vec::new() };
+
+// A weekly time window with a price rate, e.g. `Mon-Fri 22:00-06:00 70`. A window
+// that ends before it starts runs past midnight into the next day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Period {
+    days :[bool; 7],
+    start :u32,
+    end :u32,
+    percent :u32,
+}
+
+impl Period {
+    // Days are `*`, or names and ranges separated by commas, e.g. `Sat,Sun` or `Mon-Fri`.
+    pub fn parse(s :&str) -> Option<Self> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let (days, hours, percent) = match fields[..] {
+            [days, hours, percent] => (days, hours, percent),
+            _ => return None,
+        };
+        let mut period = Period { days: [days == "*"; 7], start: 0, end: 0, percent: percent.parse().ok()? };
+        let day = |name :&str| DAYS.iter().position(|d| *d == name);
+        for part in days.split(',').filter(|_| days != "*") {
+            let mut range = part.splitn(2, '-');
+            let first = day(range.next()?)?;
+            let last = match range.next() {
+                Some(name) => day(name)?,
+                None => first,
+            };
+            let mut d = first;
+            loop {
+                period.days[d] = true;
+                if d == last { break }
+                d = (d + 1) % 7;
+            }
+        }
+        let minutes = |hm :&str| {
+            let mut parts = hm.splitn(2, ':').map(|p| p.parse::<u32>().ok());
+            match (parts.next()?, parts.next()?) {
+                (Some(h), Some(m)) if m < 60 && h * 60 + m <= 24 * 60 => Some(h * 60 + m),
+                _ => None,
+            }
+        };
+        let mut hours = hours.splitn(2, '-');
+        period.start = minutes(hours.next()?)?;
+        period.end = minutes(hours.next()?)?;
+        if period.start == period.end || period.start == 24 * 60 { return None }
+        Some(period)
+    }
+
+    fn covers(&self, t :DateTime<Local>) -> bool {
+        let day = t.weekday().num_days_from_monday() as usize;
+        let secs = t.num_seconds_from_midnight();
+        let (start, end) = (self.start * 60, self.end * 60);
+        if start < end {
+            self.days[day] && start <= secs && secs < end
+        } else {
+            (self.days[day] && secs >= start) || (self.days[(day + 6) % 7] && secs < end)
+        }
+    }
+}
+
+// The rate of a server type through the week; the first period that covers a moment
+// sets its rate and any other time is at full price.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    pub periods :Vec<Period>,
+}
+
+impl Schedule {
+    pub fn percent(&self, t :DateTime<Local>) -> u32 {
+        self.periods.iter().find(|p| p.covers(t)).map_or(100, |p| p.percent)
+    }
+
+    // The first moment after `t` with a different rate, if any within a week.
+    pub fn next_change(&self, t :DateTime<Local>) -> Option<DateTime<Local>> {
+        let current = self.percent(t);
+        let today = t.naive_local().date();
+        let mut bounds = (0..=8)
+            .flat_map(|n| self.periods.iter().flat_map(move |p| {
+                let day = today + Duration::days(n);
+                vec![at(day, p.start), at(day, p.end)]
+            }))
+            .flatten()
+            .filter(|b| *b > t)
+            .collect::<Vec<_>>();
+        bounds.sort();
+        bounds.into_iter().find(|b| self.percent(*b) != current)
+    }
+
+    // Seconds between `from` and `to`, each weighted by the rate it falls in.
+    pub fn weighted_secs(&self, from :DateTime<Local>, to :DateTime<Local>) -> f64 {
+        let mut secs = 0.0;
+        let mut t = from;
+        while t < to {
+            let next = self.next_change(t).map_or(to, |n| n.min(to));
+            secs += (next - t).num_milliseconds() as f64 / 1000.0 * self.percent(t) as f64 / 100.0;
+            t = next;
+        }
+        secs
+    }
+}
+
+// `minutes` past midnight on `day`; None when the clocks skip over it.
+fn at(day :NaiveDate, minutes :u32) -> Option<DateTime<Local>> {
+    let naive = day.and_hms_opt(0, 0, 0)? + Duration::minutes(minutes as i64);
+    Local.from_local_datetime(&naive).earliest()
+}
+
+// Demand prices stay within `bounds` (lowest, highest) and follow the last `window`
+// auction clearing prices of each type; `schedules` then scale them by time of day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricingPolicy {
+    pub bounds :HashMap<ServerType, (i32, i32)>,
+    pub window :usize,
+    pub schedules :HashMap<ServerType, Schedule>,
+}
+
+impl Default for PricingPolicy {
+    fn default() -> Self {
+        PricingPolicy {
+            bounds: HashMap::new(),
+            window: 10,
+            schedules: HashMap::new(),
+        }
+    }
+}
+
+impl PricingPolicy {
+    // Half to twice the base price unless configured.
+    pub fn bounds(&self, server_type :ServerType) -> (i32, i32) {
+        let base = server_type.price();
+        self.bounds.get(&server_type).cloned().unwrap_or((base / 2, base * 2))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Pricing {
+    policy :PricingPolicy,
+    cleared :HashMap<ServerType, VecDeque<i32>>,
+}
+
+impl Pricing {
+    pub fn set_policy(&mut self, policy :PricingPolicy) {
+        for prices in self.cleared.values_mut() {
+            while prices.len() > policy.window { prices.pop_front(); }
+        }
+        self.policy = policy;
+    }
+
+    pub fn cleared(&mut self, server_type :ServerType, value :i32) {
+        let prices = self.cleared.entry(server_type).or_default();
+        prices.push_back(value);
+        while prices.len() > self.policy.window { prices.pop_front(); }
+    }
+
+    pub fn schedule(&self, server_type :ServerType) -> &Schedule {
+        self.policy.schedules.get(&server_type).unwrap_or(&FLAT)
+    }
+
+    // Oldest first, as `cleared` expects them back.
+    pub fn history(&self) -> impl Iterator<Item = (ServerType, i32)> + '_ {
+        self.cleared.iter().flat_map(|(st, prices)| prices.iter().map(move |p| (*st, *p)))
+    }
+
+    // The base price is pulled halfway towards the recent clearing average, then
+    // raised with utilisation: an idle type lists at that reference price and a
+    // sold out one at twice it. This is the rate a bought droplet keeps; what it
+    // accrues at any moment is that rate scaled by the schedule.
+    pub fn price(&self, server_type :ServerType, used :usize, total :usize) -> i32 {
+        let base = server_type.price() as f64;
+        let reference = match self.cleared.get(&server_type) {
+            Some(prices) if !prices.is_empty() =>
+                (base + prices.iter().sum::<i32>() as f64 / prices.len() as f64) / 2.0,
+            _ => base,
+        };
+        let utilisation = if total == 0 { 0.0 } else { used as f64 / total as f64 };
+        let (lowest, highest) = self.policy.bounds(server_type);
+        ((reference * (1.0 + utilisation)).round() as i32).max(lowest).min(highest)
+    }
+
+    pub fn scheduled(&self, server_type :ServerType, price :i32, t :DateTime<Local>) -> i32 {
+        (price as f64 * self.schedule(server_type).percent(t) as f64 / 100.0).round() as i32
+    }
+}
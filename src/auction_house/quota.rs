@@ -0,0 +1,75 @@
+use super::server_type::ServerType;
+
+use chrono::Duration;
+
+use std::collections::HashMap;
+
+// Limits on how much of the market one client can hold, so a single team cannot
+// buy up a type. Spend counts what the client ran up within the period: droplets
+// terminated in it plus what live ones accrued since it began. They are checked
+// when a droplet is bought or a bid placed, and again when an auction or queue
+// hands one out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QuotaPolicy {
+    pub droplets :HashMap<ServerType, usize>,
+    pub spend :Option<(f64, Duration)>,
+    pub wins :Option<(usize, Duration)>,
+}
+
+impl QuotaPolicy {
+    pub fn quotas(&self) -> Vec<Quota> {
+        let mut droplets = self.droplets.iter().collect::<Vec<_>>();
+        droplets.sort();
+        let mut quotas = droplets.into_iter().map(|(st, n)| Quota::Droplets(*st, *n)).collect::<Vec<_>>();
+        quotas.extend(self.spend.map(|(limit, period)| Quota::Spend(limit, period)));
+        quotas.extend(self.wins.map(|(n, window)| Quota::Wins(n, window)));
+        quotas
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Quota {
+    // Droplets of the type held at once.
+    Droplets(ServerType, usize),
+    // Spend within the trailing period.
+    Spend(f64, Duration),
+    // Auctions won within the window.
+    Wins(usize, Duration),
+}
+
+impl Quota {
+    pub fn name(&self) -> String {
+        match self {
+            Quota::Droplets(st, _) => format!("{:?} droplets", st),
+            Quota::Spend(_, period) => format!("Spend per {}", length(*period)),
+            Quota::Wins(_, window) => format!("Auction wins per {}", length(*window)),
+        }
+    }
+
+    pub fn limit(&self) -> f64 {
+        match self {
+            Quota::Droplets(_, n) | Quota::Wins(n, _) => *n as f64,
+            Quota::Spend(limit, _) => *limit,
+        }
+    }
+
+    // Counts are whole numbers, spend has cents.
+    pub fn amount(&self, n :f64) -> String {
+        match self {
+            Quota::Spend(_, _) => format!("{:.2}", n),
+            _ => format!("{}", n),
+        }
+    }
+}
+
+// `7d`, `24h` or `90m`: days past the first, else hours when exact.
+pub fn length(d :Duration) -> String {
+    let minutes = d.num_minutes();
+    if minutes > 24 * 60 && minutes % (24 * 60) == 0 {
+        format!("{}d", minutes / (24 * 60))
+    } else if minutes % 60 == 0 {
+        format!("{}h", minutes / 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
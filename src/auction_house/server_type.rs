@@ -6,6 +6,7 @@ pub enum ServerType {
 }
 
 impl ServerType {
+    // Base hourly price; the listed price moves around it with demand.
     pub fn price(&self) -> i32 {
         match self {
             ServerType::Slow => 20,
@@ -13,7 +14,22 @@ impl ServerType {
         }
     }
 
-    pub fn from_str(s :&str) -> Option<Self> {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            ServerType::Slow => "slow",
+            ServerType::Fast => "fast",
+        }
+    }
+
+    pub fn from_prefix(s :&str) -> Option<Self> {
+        match s {
+            "fast" => Some(ServerType::Fast),
+            "slow" => Some(ServerType::Slow),
+            _ => None,
+        }
+    }
+
+    pub fn parse(s :&str) -> Option<Self> {
         match s {
             "Fast" => Some(ServerType::Fast),
             "Slow" => Some(ServerType::Slow),
@@ -0,0 +1,231 @@
+use super::{AuctionHouse, new_auction, bid::Bid, client::Client, server_type::ServerType};
+use super::droplet::{Droplet, DropletId, DropletState};
+use super::history::{AuctionRecord, Outcome};
+use super::org::{Org, Role};
+use crate::clock::Clock;
+
+use chrono::{DateTime, Duration, Local};
+
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::sync::Arc;
+
+impl AuctionHouse {
+    pub fn snapshot<W :Write>(&self, out :&mut W) -> io::Result<()> {
+        let clients = self.clients.read();
+        let orgs = self.orgs.read();
+        let market = self.market.read();
+        for c in clients.values() {
+            writeln!(out, "client {} {} {} {}", c.email(), c.password(), c.is_admin(), c.is_suspended())?;
+        }
+        for o in orgs.values() {
+            writeln!(out, "org {}", o.name())?;
+            for (email, role, invited) in o.members() {
+                writeln!(out, "{} {} {} {}", if invited { "invite" } else { "member" }, o.name(), email, role.name())?;
+            }
+        }
+        for (st, n) in market.stock.iter() {
+            writeln!(out, "stock {:?} {}", st, n)?;
+        }
+        for (kind, reserved) in [("d", &market.reserved_d), ("a", &market.reserved_a)].iter() {
+            for d in reserved.values() {
+                writeln!(out, "droplet {} {} {:?} {} {} {} {} {} {} {} {}",
+                         kind, d.id(), d.server_type(), d.owner(), d.value(),
+                         d.stored_state().name(),
+                         d.created().to_rfc3339(),
+                         d.since().to_rfc3339(),
+                         d.billed().num_seconds(),
+                         d.accrued(),
+                         d.expires().map_or("-".into(), |t| t.to_rfc3339()))?;
+            }
+        }
+        for (owner, amount) in market.billed.iter() {
+            writeln!(out, "billed {} {}", owner, amount)?;
+        }
+        for (owner, spent) in market.spent.iter() {
+            for (t, amount) in spent {
+                writeln!(out, "spent {} {} {}", owner, t.to_rfc3339(), amount)?;
+            }
+        }
+        for (st, a) in market.auctions.iter() {
+            let bid = a.top_bid();
+            writeln!(out, "auction {:?} {} {} {} {} {} {} {}",
+                     st, a.time_left(), bid.owner(), bid.value(), a.started().to_rfc3339(), a.bid_count(),
+                     lease(&bid), bid.org().unwrap_or("-"))?;
+        }
+        for (st, q) in market.queues.iter() {
+            for bid in q.iter() {
+                writeln!(out, "queue {:?} {} {} {} {}", st, bid.owner(), bid.value(), lease(bid), bid.org().unwrap_or("-"))?;
+            }
+        }
+        for (st, value) in market.pricing.history() {
+            writeln!(out, "cleared {:?} {}", st, value)?;
+        }
+        for r in market.history.iter() {
+            writeln!(out, "closed {:?} {} {} {} {} {} {}",
+                     r.server_type, r.outcome.name(), r.bidder, r.price, r.bids,
+                     r.started.to_rfc3339(), r.closed.to_rfc3339())?;
+        }
+        Ok(())
+    }
+
+    // Leases stay paused until `resume_leases`, so the lease settings can be applied first.
+    pub fn restore<R :BufRead>(input :R, clock :Arc<dyn Clock>) -> io::Result<Arc<AuctionHouse>> {
+        let ah = AuctionHouse::new(clock);
+        let mut auctions = Vec::new();
+        {
+            let mut clients = ah.clients.write();
+            let mut orgs = ah.orgs.write();
+            let mut market = ah.market.write();
+            for (n, line) in input.lines().enumerate() {
+                let line = line?;
+                let invalid = || io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("snapshot line {}: {}", n + 1, line));
+                let time = |s :&str| DateTime::parse_from_rfc3339(s)
+                    .map(|t| t.with_timezone(&Local))
+                    .map_err(|_| invalid());
+                // Snapshots from before leases have no lease fields at all.
+                let lease = |s :Option<&&str>| match s {
+                    None | Some(&"-") => Ok(None),
+                    Some(secs) => secs.parse().map(|s| Some(Duration::seconds(s))).map_err(|_| invalid()),
+                };
+                let fields = line.split(' ').collect::<Vec<&str>>();
+                match fields.as_slice() {
+                    ["client", email, password, admin, suspended] => {
+                        let mut c = if admin.parse().map_err(|_| invalid())? {
+                            Client::new_admin(email.to_string(), password.to_string())
+                        } else {
+                            Client::new(email.to_string(), password.to_string())
+                        };
+                        c.set_suspended(suspended.parse().map_err(|_| invalid())?);
+                        clients.insert(email.to_string(), c);
+                    },
+                    ["org", name] => {
+                        orgs.insert(name.to_string(), Org::restore(name));
+                    },
+                    [kind @ ("member" | "invite"), name, email, role] => {
+                        let o = orgs.get_mut(*name).ok_or_else(invalid)?;
+                        let role = Role::parse(role).ok_or_else(invalid)?;
+                        if *kind == "member" { o.add(email, role) } else { o.invite(email, role) }
+                    },
+                    ["stock", st, amount] => {
+                        let st = ServerType::parse(st).ok_or_else(invalid)?;
+                        market.stock.insert(st, amount.parse().map_err(|_| invalid())?);
+                    },
+                    ["droplet", kind, id, st, owner, value, lifecycle @ ..] => {
+                        let st = ServerType::parse(st).ok_or_else(invalid)?;
+                        // Older snapshots numbered droplets; those get a fresh id.
+                        let id = match DropletId::parse(id) {
+                            Some(id) if id.server_type() == st => id,
+                            Some(_) => return Err(invalid()),
+                            None => {
+                                id.parse::<u32>().map_err(|_| invalid())?;
+                                DropletId::new(st)
+                            },
+                        };
+                        let mut d = Droplet::restore(
+                            id,
+                            owner,
+                            value.parse().map_err(|_| invalid())?,
+                            ah.now());
+                        // Snapshots from before droplets had states carry no lifecycle,
+                        // and those from before schedules bill at the flat rate.
+                        if let [state, created, since, billed, rest @ ..] = lifecycle {
+                            let billed = billed.parse::<i64>().map_err(|_| invalid())?;
+                            let accrued = match rest {
+                                [] => billed as f64 * d.value() as f64 / 3600.0,
+                                [accrued, ..] => accrued.parse().map_err(|_| invalid())?,
+                            };
+                            match rest {
+                                [] | [_] | [_, "-"] => (),
+                                [_, expires] => d.set_expires(Some(time(expires)?)),
+                                _ => return Err(invalid()),
+                            }
+                            d = d.with_lifecycle(
+                                DropletState::parse(state).ok_or_else(invalid)?,
+                                time(created)?,
+                                time(since)?,
+                                Duration::seconds(billed),
+                                accrued);
+                        } else if !lifecycle.is_empty() {
+                            return Err(invalid())
+                        }
+                        match *kind {
+                            "d" => market.reserved_d.insert(d.id(), d),
+                            "a" => market.reserved_a.insert(d.id(), d),
+                            _ => return Err(invalid()),
+                        };
+                    },
+                    ["auction", st, delay, owner, value, resumed @ ..] => {
+                        // Older snapshots did not keep the start or the bid count.
+                        let started = match resumed {
+                            [] => None,
+                            [started, bids, ..] if resumed.len() <= 4 =>
+                                Some((time(started)?, bids.parse().map_err(|_| invalid())?)),
+                            _ => return Err(invalid()),
+                        };
+                        auctions.push((
+                            ServerType::parse(st).ok_or_else(invalid)?,
+                            delay.parse().map_err(|_| invalid())?,
+                            Bid::new(owner, value.parse().map_err(|_| invalid())?)
+                                .with_lease(lease(resumed.get(2))?)
+                                .with_org(org(resumed.get(3))),
+                            started));
+                    },
+                    ["closed", st, outcome, bidder, price, bids, started, closed] => {
+                        market.history.record(AuctionRecord {
+                            server_type: ServerType::parse(st).ok_or_else(invalid)?,
+                            outcome: Outcome::parse(outcome).ok_or_else(invalid)?,
+                            bidder: bidder.to_string(),
+                            price: price.parse().map_err(|_| invalid())?,
+                            bids: bids.parse().map_err(|_| invalid())?,
+                            started: time(started)?,
+                            closed: time(closed)?,
+                        });
+                    },
+                    ["billed", owner, amount] => {
+                        market.billed.insert(owner.to_string(), amount.parse().map_err(|_| invalid())?);
+                    },
+                    ["spent", owner, t, amount] => {
+                        market.spent
+                            .entry(owner.to_string())
+                            .or_default()
+                            .push((time(t)?, amount.parse().map_err(|_| invalid())?));
+                    },
+                    ["queue", st, owner, value, rest @ ..] if rest.len() <= 2 => {
+                        market.queues
+                            .entry(ServerType::parse(st).ok_or_else(invalid)?)
+                            .or_default()
+                            .enqueue(Bid::new(owner, value.parse().map_err(|_| invalid())?)
+                                     .with_lease(lease(rest.first())?)
+                                     .with_org(org(rest.get(1))));
+                    },
+                    ["cleared", st, value] => {
+                        market.pricing.cleared(
+                            ServerType::parse(st).ok_or_else(invalid)?,
+                            value.parse().map_err(|_| invalid())?);
+                    },
+                    [""] => (),
+                    _ => return Err(invalid()),
+                }
+            }
+        }
+        for (st, delay, bid, resumed) in auctions {
+            let mut auction = new_auction(&ah, st, bid, delay);
+            if let Some((started, bids)) = resumed {
+                auction = auction.resumed(started, bids);
+            }
+            ah.market.write().auctions.insert(st, auction);
+        }
+        Ok(ah)
+    }
+}
+
+// A bid's org; snapshots from before orgs have no field for it.
+fn org<'a>(field :Option<&&'a str>) -> Option<&'a str> {
+    field.filter(|o| **o != "-").copied()
+}
+
+fn lease(bid :&Bid) -> String {
+    bid.lease().map_or("-".into(), |lease| lease.num_seconds().to_string())
+}
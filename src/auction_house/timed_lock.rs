@@ -0,0 +1,72 @@
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// A panic while a guard is held poisons the lock. Instead of failing every later
+// caller, the next one to take the lock runs `check` over the data to repair what
+// the interrupted update may have left behind, then clears the poison.
+#[derive(Debug)]
+pub struct TimedRwLock<T> {
+    lock :RwLock<T>,
+    wait_ns :AtomicU64,
+    acquisitions :AtomicU64,
+    recoveries :AtomicU64,
+    check :fn(&mut T) -> usize,
+}
+
+impl<T> TimedRwLock<T> {
+    pub fn with_check(t :T, check :fn(&mut T) -> usize) -> Self {
+        TimedRwLock {
+            lock: RwLock::new(t),
+            wait_ns: AtomicU64::new(0),
+            acquisitions: AtomicU64::new(0),
+            recoveries: AtomicU64::new(0),
+            check,
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let start = Instant::now();
+        if self.lock.is_poisoned() {
+            drop(self.write_unrecorded());
+        }
+        let guard = self.lock.read().unwrap_or_else(PoisonError::into_inner);
+        self.record(start);
+        guard
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.write_unrecorded();
+        self.record(start);
+        guard
+    }
+
+    pub fn stats(&self) -> (u64, Duration) {
+        (self.acquisitions.load(Ordering::Relaxed),
+         Duration::from_nanos(self.wait_ns.load(Ordering::Relaxed)))
+    }
+
+    pub fn recoveries(&self) -> u64 {
+        self.recoveries.load(Ordering::Relaxed)
+    }
+
+    fn write_unrecorded(&self) -> RwLockWriteGuard<'_, T> {
+        match self.lock.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => {
+                let mut guard = poisoned.into_inner();
+                let repaired = (self.check)(&mut guard);
+                self.lock.clear_poison();
+                self.recoveries.fetch_add(1, Ordering::Relaxed);
+                eprintln!("recovered poisoned lock, {} entries repaired", repaired);
+                guard
+            },
+        }
+    }
+
+    fn record(&self, start :Instant) {
+        self.wait_ns.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -1,6 +1,47 @@
+use super::bid::Bid;
+
 use std::collections::BinaryHeap;
 
+// Bids waiting for stock, highest first. Each client holds at most one place:
+// bidding again replaces the earlier bid.
+#[derive(Debug, Default)]
 pub struct UniqueBidQueue {
-    bids :BinaryHeap,
+    bids :BinaryHeap<Bid>,
+}
+
+impl UniqueBidQueue {
+    // Returns true when an earlier bid from the same client was replaced.
+    pub fn enqueue(&mut self, bid :Bid) -> bool {
+        let replaced = self.remove(bid.owner());
+        self.bids.push(bid);
+        replaced
+    }
+
+    pub fn pop(&mut self) -> Option<Bid> {
+        self.bids.pop()
+    }
+
+    pub fn remove(&mut self, owner :&str) -> bool {
+        let before = self.bids.len();
+        self.bids.retain(|b| b.owner() != owner);
+        self.bids.len() < before
+    }
+
+    // 1-based place of the client's bid, counting only strictly higher bids ahead.
+    pub fn position(&self, owner :&str) -> Option<usize> {
+        let bid = self.bids.iter().find(|b| b.owner() == owner)?;
+        Some(self.bids.iter().filter(|b| *b > bid).count() + 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty()
+    }
 
+    pub fn iter(&self) -> impl Iterator<Item = &Bid> {
+        self.bids.iter()
+    }
 }
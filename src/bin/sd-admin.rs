@@ -0,0 +1,31 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::process;
+
+const USAGE :&str = "Usage: sd-admin [-s <socket>] <dump|reload|sessions|drain|snapshot|health>";
+
+fn main() -> io::Result<()> {
+    let mut args = env::args().skip(1).collect::<Vec<String>>();
+    let mut socket = env::var("SD_CONTROL_SOCKET").unwrap_or_else(|_| "sd-rust.sock".into());
+    if args.len() >= 2 && args[0] == "-s" {
+        socket = args.remove(1);
+        args.remove(0);
+    }
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    let mut stream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("sd-admin: cannot connect to {}: {}", socket, e);
+            process::exit(1);
+        },
+    };
+    stream.write_all((args.join(" ") + "\n").as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    print!("{}", response);
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process;
+
+const USAGE :&str = "Usage: sd-audit [-f <log>] [--account <email>] [--since <time>] [--until <time>]
+\ttimes are RFC 3339, \"YYYY-MM-DD HH:MM:SS\" or \"YYYY-MM-DD\" in local time";
+
+fn parse_time(s :&str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(s).ok()
+        .or_else(|| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok()
+                 .and_then(|t| Local.from_local_datetime(&t).single())
+                 .map(|t| t.with_timezone(t.offset())))
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()
+                 .and_then(|d| d.and_hms_opt(0, 0, 0))
+                 .and_then(|t| Local.from_local_datetime(&t).single())
+                 .map(|t| t.with_timezone(t.offset())))
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn main() -> io::Result<()> {
+    let mut log = PathBuf::from("sd-rust.audit.log");
+    let mut account = None;
+    let mut since = None;
+    let mut until = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "-h" | "--help" => usage(),
+            _ => args.next().unwrap_or_else(|| usage()),
+        };
+        match arg.as_str() {
+            "-f" => log = value.into(),
+            "--account" => account = Some(value),
+            "--since" => since = Some(parse_time(&value).unwrap_or_else(|| usage())),
+            "--until" => until = Some(parse_time(&value).unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+    // Rotated files hold older records: log.N is the oldest, log the newest.
+    let mut files = (1..)
+        .map(|n| {
+            let mut name = log.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        })
+        .take_while(|p| p.exists())
+        .collect::<Vec<_>>();
+    files.reverse();
+    files.push(log);
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for path in files.iter().filter(|p| p.exists()) {
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            if fields.len() < 6 { continue }
+            let time = match DateTime::parse_from_rfc3339(fields[0]) {
+                Ok(time) => time,
+                Err(_) => continue,
+            };
+            if account.as_ref().map(|a| a != fields[2]).unwrap_or(false)
+                || since.map(|s| time < s).unwrap_or(false)
+                || until.map(|u| time > u).unwrap_or(false) {
+                continue
+            }
+            writeln!(out, "{}", line)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Local};
+
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub type Job = Box<dyn FnOnce() + Send>;
+
+pub type TimerId = u64;
+
+// Where timers, auctions and billing get the time from.
+pub trait Clock :Send + Sync + fmt::Debug {
+    fn now(&self) -> DateTime<Local>;
+
+    // Runs `job` once `delay` has passed on this clock.
+    fn schedule(&self, delay :Duration, job :Job) -> TimerId;
+
+    // Drops a job that has not run yet; one that has is left alone.
+    fn cancel(&self, id :TimerId);
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn schedule(&self, delay :Duration, job :Job) -> TimerId {
+        timer().schedule(Instant::now() + delay, job)
+    }
+
+    fn cancel(&self, id :TimerId) {
+        timer().jobs.lock().unwrap().retain(|t| t.n != id);
+    }
+}
+
+struct Timed {
+    due :Instant,
+    n :u64,
+    job :Job,
+}
+
+// Ordered so that the heap's top is the job due first.
+impl Ord for Timed {
+    fn cmp(&self, other :&Self) -> cmp::Ordering {
+        (other.due, other.n).cmp(&(self.due, self.n))
+    }
+}
+
+impl PartialOrd for Timed {
+    fn partial_cmp(&self, other :&Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Timed {
+    fn eq(&self, other :&Self) -> bool {
+        (self.due, self.n) == (other.due, other.n)
+    }
+}
+
+impl Eq for Timed {}
+
+// Every job scheduled on the system clock waits in one heap, watched by one thread
+// that sleeps until the first is due. Each job then runs on a thread of its own,
+// so a slow one holds up no other timer.
+#[derive(Default)]
+struct Timer {
+    jobs :Mutex<BinaryHeap<Timed>>,
+    added :Condvar,
+    scheduled :AtomicU64,
+}
+
+fn timer() -> &'static Timer {
+    static TIMER :OnceLock<Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        // The thread's own call to `timer` waits until this one has returned.
+        thread::spawn(|| timer().run());
+        Timer::default()
+    })
+}
+
+impl Timer {
+    fn schedule(&self, due :Instant, job :Job) -> TimerId {
+        let n = self.scheduled.fetch_add(1, Ordering::SeqCst);
+        self.jobs.lock().unwrap().push(Timed { due, n, job });
+        self.added.notify_one();
+        n
+    }
+
+    fn run(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            jobs = match jobs.peek().map(|t| t.due) {
+                Some(due) if due <= now => {
+                    let job = jobs.pop().unwrap().job;
+                    thread::spawn(job);
+                    jobs
+                },
+                Some(due) => self.added.wait_timeout(jobs, due - now).unwrap().0,
+                None => self.added.wait(jobs).unwrap(),
+            };
+        }
+    }
+}
+
+// Time only moves when `advance` is called, and timers that come due run on the
+// advancing thread in the order they were due, so tests are repeatable.
+pub struct ManualClock {
+    now :Mutex<DateTime<Local>>,
+    timers :Mutex<Vec<(DateTime<Local>, u64, Job)>>,
+    scheduled :AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(start :DateTime<Local>) -> Self {
+        ManualClock {
+            now: Mutex::new(start),
+            timers: Mutex::new(Vec::new()),
+            scheduled: AtomicU64::new(0),
+        }
+    }
+
+    pub fn advance(&self, by :Duration) {
+        let target = *self.now.lock().unwrap() + chrono::Duration::from_std(by).unwrap();
+        loop {
+            // Jobs may schedule more jobs, so none run while `timers` is locked.
+            let job = {
+                let mut timers = self.timers.lock().unwrap();
+                let next = timers.iter()
+                    .enumerate()
+                    .filter(|(_, (due, _, _))| *due <= target)
+                    .min_by_key(|(_, (due, n, _))| (*due, *n))
+                    .map(|(i, _)| i);
+                next.map(|i| timers.remove(i))
+            };
+            match job {
+                Some((due, _, job)) => {
+                    *self.now.lock().unwrap() = due;
+                    job();
+                },
+                None => break,
+            }
+        }
+        *self.now.lock().unwrap() = target;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Local> {
+        *self.now.lock().unwrap()
+    }
+
+    fn schedule(&self, delay :Duration, job :Job) -> TimerId {
+        let due = self.now() + chrono::Duration::from_std(delay).unwrap();
+        let n = self.scheduled.fetch_add(1, Ordering::SeqCst);
+        self.timers.lock().unwrap().push((due, n, job));
+        n
+    }
+
+    fn cancel(&self, id :TimerId) {
+        self.timers.lock().unwrap().retain(|(_, n, _)| *n != id);
+    }
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, f :&mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ManualClock")
+            .field("now", &self.now())
+            .field("timers", &self.timers.lock().unwrap().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    #[test]
+    fn system_clock_runs_jobs_when_due() {
+        let (tx, rx) = mpsc::channel();
+        let start = Instant::now();
+        for (n, ms) in [(3, 150), (1, 50), (2, 100), (0, 0)] {
+            let tx = tx.clone();
+            SystemClock.schedule(Duration::from_millis(ms), Box::new(move || tx.send((n, Instant::now())).unwrap()));
+        }
+        let ran = (0..4).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect::<Vec<_>>();
+        assert_eq!(ran.iter().map(|(n, _)| *n).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert!(ran[3].1 - start >= Duration::from_millis(150));
+
+        let (tx, rx) = mpsc::channel();
+        let cancelled = tx.clone();
+        let id = SystemClock.schedule(Duration::from_millis(50), Box::new(move || cancelled.send(0).unwrap()));
+        SystemClock.schedule(Duration::from_millis(100), Box::new(move || tx.send(1).unwrap()));
+        SystemClock.cancel(id);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 1);
+    }
+}
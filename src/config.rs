@@ -0,0 +1,222 @@
+use crate::auction_house::{AUCTION_DURATION, LEASE_WARNING, lockout::LockoutPolicy, pricing::{Period, PricingPolicy}, quota::QuotaPolicy, server_type::ServerType};
+use crate::rate_limit::{CommandClass, Rate};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// What happens to running auctions on shutdown. They are settled either way when
+// there is no snapshot to save them in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShutdownAuctions {
+    Save,
+    Settle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerMode {
+    Threads,
+    Events,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub listen :String,
+    pub control_socket :PathBuf,
+    pub metrics_listen :String,
+    pub snapshot :PathBuf,
+    pub audit_log :PathBuf,
+    pub audit_max_bytes :u64,
+    pub audit_keep :usize,
+    pub drain_timeout :u64,
+    pub auctions_on_shutdown :ShutdownAuctions,
+    pub auction_duration :usize,
+    pub lease_warning :usize,
+    pub mode :ServerMode,
+    pub workers :usize,
+    pub event_loops :usize,
+    pub max_connections :usize,
+    pub max_connections_per_ip :usize,
+    pub idle_timeout :u64,
+    pub rates :HashMap<CommandClass, Rate>,
+    pub login :LockoutPolicy,
+    pub pricing :PricingPolicy,
+    pub quotas :QuotaPolicy,
+    pub stock :Vec<(ServerType, u32)>,
+    pub admins :Vec<(String, String)>,
+}
+
+// Just the game server: no control socket, metrics, snapshot or audit log, so
+// that several can run in one process. `standalone` turns them on.
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: "127.0.0.1:12345".into(),
+            control_socket: PathBuf::new(),
+            metrics_listen: String::new(),
+            snapshot: PathBuf::new(),
+            audit_log: PathBuf::new(),
+            audit_max_bytes: 10 * 1024 * 1024,
+            audit_keep: 5,
+            drain_timeout: 10,
+            auctions_on_shutdown: ShutdownAuctions::Save,
+            auction_duration: AUCTION_DURATION,
+            lease_warning: LEASE_WARNING,
+            mode: ServerMode::Threads,
+            workers: 64,
+            event_loops: 4,
+            max_connections: 256,
+            max_connections_per_ip: 16,
+            idle_timeout: 300,
+            rates: Rate::defaults(),
+            login: LockoutPolicy::default(),
+            pricing: PricingPolicy::default(),
+            quotas: QuotaPolicy::default(),
+            stock: vec![(ServerType::Slow, 30), (ServerType::Fast, 4)],
+            admins: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // What `sd-rust` runs with before its config file is read, side channels and
+    // all, in the working directory.
+    pub fn standalone() -> Config {
+        Config {
+            control_socket: "sd-rust.sock".into(),
+            metrics_listen: "127.0.0.1:9898".into(),
+            snapshot: "sd-rust.snapshot".into(),
+            audit_log: "sd-rust.audit.log".into(),
+            ..Config::default()
+        }
+    }
+
+    // Keys the file leaves out keep their `standalone` values.
+    pub fn load(path :&Path) -> io::Result<Config> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Config::standalone()),
+            Err(e) => return Err(e),
+        };
+        let mut config = Config::standalone();
+        let mut stock = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue }
+            let invalid = || io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}:{}: invalid line: {}", path.display(), n + 1, line));
+            let mut kv = line.splitn(2, '=').map(|s| s.trim());
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) => (k, v),
+                _ => return Err(invalid()),
+            };
+            match key {
+                "listen" => config.listen = value.into(),
+                "control_socket" => config.control_socket = value.into(),
+                "metrics_listen" => config.metrics_listen = value.into(),
+                "snapshot" => config.snapshot = value.into(),
+                "audit_log" => config.audit_log = value.into(),
+                "audit_max_bytes" => config.audit_max_bytes = value.parse().map_err(|_| invalid())?,
+                "audit_keep" => config.audit_keep = value.parse().map_err(|_| invalid())?,
+                "drain_timeout" => config.drain_timeout = value.parse().map_err(|_| invalid())?,
+                "auctions_on_shutdown" => config.auctions_on_shutdown = match value {
+                    "save" => ShutdownAuctions::Save,
+                    "settle" => ShutdownAuctions::Settle,
+                    _ => return Err(invalid()),
+                },
+                "auction_duration" => config.auction_duration = value.parse().map_err(|_| invalid())?,
+                "lease_warning" => config.lease_warning = value.parse().map_err(|_| invalid())?,
+                "mode" => config.mode = match value {
+                    "threads" => ServerMode::Threads,
+                    "events" => ServerMode::Events,
+                    _ => return Err(invalid()),
+                },
+                "workers" => config.workers = value.parse().map_err(|_| invalid())?,
+                "event_loops" => config.event_loops = value.parse().map_err(|_| invalid())?,
+                "max_connections" => config.max_connections = value.parse().map_err(|_| invalid())?,
+                "max_connections_per_ip" => config.max_connections_per_ip = value.parse().map_err(|_| invalid())?,
+                "idle_timeout" => config.idle_timeout = value.parse().map_err(|_| invalid())?,
+                "login_max_failures" => config.login.max_failures = value.parse().map_err(|_| invalid())?,
+                "login_max_failures_per_ip" =>
+                    config.login.max_failures_per_ip = value.parse().map_err(|_| invalid())?,
+                "login_delay" =>
+                    config.login.base_delay = Duration::from_secs(value.parse().map_err(|_| invalid())?),
+                "login_lockout" =>
+                    config.login.lockout = Duration::from_secs(value.parse().map_err(|_| invalid())?),
+                "admin" => {
+                    let mut ep = value.splitn(2, ':');
+                    match (ep.next(), ep.next()) {
+                        (Some(e), Some(p)) if !e.is_empty() => config.admins.push((e.into(), p.into())),
+                        _ => return Err(invalid()),
+                    }
+                },
+                k if k.starts_with("rate.") => {
+                    let class = CommandClass::parse(&k["rate.".len()..]).ok_or_else(invalid)?;
+                    let mut parts = value.split_whitespace().map(|v| v.parse::<f64>());
+                    match (parts.next(), parts.next(), parts.next()) {
+                        (Some(Ok(burst)), Some(Ok(per_sec)), None) if Rate::new(burst, per_sec).valid() => {
+                            config.rates.insert(class, Rate::new(burst, per_sec));
+                        },
+                        _ => return Err(invalid()),
+                    }
+                },
+                "price_window" => config.pricing.window = value.parse().map_err(|_| invalid())?,
+                k if k.starts_with("price.") => {
+                    let st = ServerType::parse(&k["price.".len()..]).ok_or_else(invalid)?;
+                    let mut parts = value.split_whitespace().map(|v| v.parse::<i32>());
+                    match (parts.next(), parts.next(), parts.next()) {
+                        (Some(Ok(lowest)), Some(Ok(highest)), None) if 0 < lowest && lowest <= highest => {
+                            config.pricing.bounds.insert(st, (lowest, highest));
+                        },
+                        _ => return Err(invalid()),
+                    }
+                },
+                // Repeat the key for more periods; the first that matches wins.
+                k if k.starts_with("schedule.") => {
+                    let st = ServerType::parse(&k["schedule.".len()..]).ok_or_else(invalid)?;
+                    let period = Period::parse(value).ok_or_else(invalid)?;
+                    config.pricing.schedules.entry(st).or_default().periods.push(period);
+                },
+                k if k.starts_with("quota.droplets.") => {
+                    let st = ServerType::parse(&k["quota.droplets.".len()..]).ok_or_else(invalid)?;
+                    config.quotas.droplets.insert(st, value.parse().map_err(|_| invalid())?);
+                },
+                // An amount and the period it covers, e.g. `500 24h`.
+                "quota.spend" => {
+                    let (limit, period) = value.split_once(' ').ok_or_else(invalid)?;
+                    let limit = limit.parse::<f64>().ok().filter(|l| *l >= 0.0).ok_or_else(invalid)?;
+                    config.quotas.spend = Some((limit, parse_length(period.trim()).ok_or_else(invalid)?));
+                },
+                "quota.wins" => {
+                    let (n, window) = value.split_once(' ').ok_or_else(invalid)?;
+                    let n = n.parse().map_err(|_| invalid())?;
+                    config.quotas.wins = Some((n, parse_length(window.trim()).ok_or_else(invalid)?));
+                },
+                k if k.starts_with("stock.") => {
+                    let st = ServerType::parse(&k["stock.".len()..]).ok_or_else(invalid)?;
+                    stock.push((st, value.parse().map_err(|_| invalid())?));
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        if !stock.is_empty() {
+            config.stock = stock;
+        }
+        Ok(config)
+    }
+}
+
+// `30m`, `24h` or `7d`.
+pub(crate) fn parse_length(s :&str) -> Option<chrono::Duration> {
+    let unit = s.chars().last()?;
+    let n = s[..s.len() - unit.len_utf8()].parse::<i64>().ok().filter(|n| 0 < *n && *n <= 100_000)?;
+    match unit {
+        'm' => Some(chrono::Duration::minutes(n)),
+        'h' => Some(chrono::Duration::hours(n)),
+        'd' => Some(chrono::Duration::days(n)),
+        _ => None,
+    }
+}
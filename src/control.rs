@@ -0,0 +1,91 @@
+use crate::server::Server;
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// How long an operator has to send a command, and the reply to be read.
+const TIMEOUT :Duration = Duration::from_secs(5);
+
+pub fn listen(server :Arc<Server>, path :&Path) -> io::Result<()> {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != ErrorKind::NotFound { return Err(e) }
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    // Each connection gets a thread, so one that never sends a command does not
+    // hold up the others, `drain` among them.
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = Arc::clone(&server);
+                    thread::spawn(move || {
+                        if let Err(e) = handle(&server, stream) {
+                            eprintln!("control: {:?}", e);
+                        }
+                    });
+                },
+                Err(e) => eprintln!("control: {:?}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle(server :&Server, stream :UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = run_command(server, line.trim());
+    (&stream).write_all(response.as_bytes())?;
+    (&stream).write_all(b"\n")
+}
+
+fn run_command(server :&Server, command :&str) -> String {
+    match command {
+        "dump" => server.auction_house().dump(),
+        "reload" => server.reload().unwrap_or_else(|e| format!("Reload failed: {}", e)),
+        "sessions" => {
+            "ID\tPeer\tUser\tConnected since\n=========================\n".to_string()
+                + &server.sessions().list()
+                .iter()
+                .map(|s| format!("{}\t{}\t{}\t{}\n",
+                                 s.id,
+                                 s.peer.map(|p| p.to_string()).unwrap_or_else(|| "-".into()),
+                                 s.user.as_deref().unwrap_or("-"),
+                                 s.since.format("%Y-%m-%d %H:%M:%S")))
+                .collect::<String>()
+        },
+        "drain" if server.is_draining() => "Already draining".into(),
+        "drain" => {
+            server.drain();
+            format!("Draining {} sessions", server.sessions().len())
+        },
+        "snapshot" => match server.snapshot() {
+            Ok(path) => format!("Snapshot written to {}", path.display()),
+            Err(e) => format!("Snapshot failed: {}", e),
+        },
+        "health" => {
+            let ah = server.auction_house();
+            if ah.healthy() {
+                "ok".into()
+            } else {
+                ah.stats().recoveries
+                    .iter()
+                    .filter(|(_, n)| *n > 0)
+                    .map(|(name, n)| format!("degraded: {} lock recovered after a panic {} time(s)", name, n))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        },
+        "" => "Usage: dump | reload | sessions | drain | snapshot | health".into(),
+        s => format!("Command not found: {}", s),
+    }
+}
@@ -0,0 +1,248 @@
+use crate::server::Server;
+use crate::session::{Output, Session, IDLE_TIMEOUT};
+
+use mio::{Events, Interest, Poll, Token, Waker};
+use mio::net::TcpStream;
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{self, SocketAddr};
+use std::sync::{Arc, Mutex, mpsc, atomic::AtomicBool, atomic::AtomicUsize, atomic::Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const WAKE :Token = Token(0);
+const SWEEP_INTERVAL :Duration = Duration::from_secs(1);
+// Output a client may leave unread before its connection is dropped.
+const MAX_PENDING :usize = 1024 * 1024;
+// How long a closed connection may leave its last output unread before it is dropped.
+const CLOSE_GRACE :Duration = Duration::from_secs(5);
+
+static NEXT_TOKEN :AtomicUsize = AtomicUsize::new(1);
+
+#[derive(Debug)]
+struct EventOutput {
+    token :Token,
+    buf :Mutex<Vec<u8>>,
+    closed :AtomicBool,
+    dirty :Arc<Mutex<HashSet<Token>>>,
+    waker :Arc<Waker>,
+}
+
+impl Output for EventOutput {
+    fn send(&self, msg :&str) -> io::Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(io::Error::new(ErrorKind::NotConnected, "connection closed"))
+        }
+        {
+            let mut buf = self.buf.lock().unwrap();
+            buf.extend_from_slice(msg.as_bytes());
+            buf.push(b'\n');
+        }
+        self.dirty.lock().unwrap().insert(self.token);
+        self.waker.wake()
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.dirty.lock().unwrap().insert(self.token);
+        let _ = self.waker.wake();
+    }
+}
+
+struct Conn {
+    stream :TcpStream,
+    session :Session,
+    out :Arc<EventOutput>,
+    pending :Vec<u8>,
+    last_active :Instant,
+    closing :bool,
+    closing_since :Option<Instant>,
+}
+
+struct LoopHandle {
+    sender :mpsc::Sender<(TcpStream, Session, Arc<EventOutput>)>,
+    dirty :Arc<Mutex<HashSet<Token>>>,
+    waker :Arc<Waker>,
+    thread :JoinHandle<()>,
+}
+
+pub struct EventLoops {
+    server :Arc<Server>,
+    loops :Vec<LoopHandle>,
+    next :usize,
+}
+
+impl EventLoops {
+    pub fn start(server :Arc<Server>, count :usize) -> io::Result<Self> {
+        let mut loops = Vec::new();
+        for _ in 0..count.max(1) {
+            let poll = Poll::new()?;
+            let waker = Arc::new(Waker::new(poll.registry(), WAKE)?);
+            let dirty = Arc::new(Mutex::new(HashSet::new()));
+            let (sender, receiver) = mpsc::channel();
+            let server = Arc::clone(&server);
+            let loop_dirty = Arc::clone(&dirty);
+            let thread = thread::spawn(move || {
+                if let Err(e) = run_loop(server, poll, receiver, loop_dirty) {
+                    eprintln!("event loop: {:?}", e);
+                }
+            });
+            loops.push(LoopHandle { sender, dirty, waker, thread });
+        }
+        Ok(EventLoops { server, loops, next: 0 })
+    }
+
+    pub fn add(&mut self, stream :net::TcpStream, peer :SocketAddr) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        let handle = &self.loops[self.next % self.loops.len()];
+        self.next = self.next.wrapping_add(1);
+        let out = Arc::new(EventOutput {
+            token: Token(NEXT_TOKEN.fetch_add(1, Ordering::SeqCst)),
+            buf: Mutex::new(Vec::new()),
+            closed: AtomicBool::new(false),
+            dirty: Arc::clone(&handle.dirty),
+            waker: Arc::clone(&handle.waker),
+        });
+        let session = Session::new(
+            Arc::clone(self.server.auction_house()),
+            Arc::clone(self.server.sessions()),
+            Some(peer),
+            Arc::clone(&out) as Arc<dyn Output>);
+        handle.sender.send((TcpStream::from_std(stream), session, out))
+            .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "event loop has stopped"))?;
+        handle.waker.wake()
+    }
+
+    pub fn join(self) {
+        for handle in self.loops {
+            drop(handle.sender);
+            let _ = handle.waker.wake();
+            let _ = handle.thread.join();
+        }
+    }
+}
+
+fn run_loop(
+    server :Arc<Server>,
+    mut poll :Poll,
+    incoming :mpsc::Receiver<(TcpStream, Session, Arc<EventOutput>)>,
+    dirty :Arc<Mutex<HashSet<Token>>>) -> io::Result<()> {
+
+    let mut events = Events::with_capacity(1024);
+    let mut conns :HashMap<Token, Conn> = HashMap::new();
+    let mut buf = [0; 1024];
+    let mut accepting = true;
+    let mut last_sweep = Instant::now();
+    while accepting || !conns.is_empty() {
+        if let Err(e) = poll.poll(&mut events, Some(SWEEP_INTERVAL)) {
+            if e.kind() == ErrorKind::Interrupted { continue }
+            return Err(e)
+        }
+        for event in events.iter() {
+            let token = event.token();
+            if token == WAKE { continue }
+            let conn = match conns.get_mut(&token) {
+                Some(conn) => conn,
+                None => continue,
+            };
+            if event.is_readable() {
+                loop {
+                    match conn.stream.read(&mut buf) {
+                        Ok(0) => { conn.closing = true; break },
+                        Ok(n) => {
+                            conn.last_active = Instant::now();
+                            if !conn.session.handle_input(&String::from_utf8_lossy(&buf[..n])) {
+                                conn.closing = true;
+                                break
+                            }
+                        },
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(_) => { conn.closing = true; break },
+                    }
+                }
+            }
+            if event.is_writable() || conn.closing {
+                flush(&poll, conn);
+            }
+        }
+        loop {
+            match incoming.try_recv() {
+                Ok((mut stream, session, out)) => {
+                    poll.registry().register(&mut stream, out.token, Interest::READABLE)?;
+                    conns.insert(out.token, Conn {
+                        stream,
+                        session,
+                        out,
+                        pending: Vec::new(),
+                        last_active: Instant::now(),
+                        closing: false,
+                        closing_since: None,
+                    });
+                },
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => { accepting = false; break },
+            }
+        }
+        let tokens = dirty.lock().unwrap().drain().collect::<Vec<_>>();
+        for token in tokens {
+            if let Some(conn) = conns.get_mut(&token) {
+                if conn.out.closed.load(Ordering::SeqCst) { conn.closing = true }
+                flush(&poll, conn);
+            }
+        }
+        if last_sweep.elapsed() >= SWEEP_INTERVAL {
+            last_sweep = Instant::now();
+            let idle_timeout = server.config().idle_timeout;
+            for conn in conns.values_mut() {
+                if conn.closing {
+                    // Still not read since it was closed; what is left is given up on.
+                    match conn.closing_since {
+                        Some(since) if since.elapsed() >= CLOSE_GRACE => conn.pending.clear(),
+                        Some(_) => {},
+                        None => conn.closing_since = Some(Instant::now()),
+                    }
+                } else if idle_timeout > 0 && conn.last_active.elapsed() >= Duration::from_secs(idle_timeout) {
+                    let _ = conn.out.send(IDLE_TIMEOUT);
+                    conn.closing = true;
+                    flush(&poll, conn);
+                }
+            }
+        }
+        let closed = conns.iter()
+            .filter(|(_, c)| c.closing && c.pending.is_empty())
+            .map(|(t, _)| *t)
+            .collect::<Vec<_>>();
+        for token in closed {
+            if let Some(mut conn) = conns.remove(&token) {
+                conn.out.closed.store(true, Ordering::SeqCst);
+                let _ = poll.registry().deregister(&mut conn.stream);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn flush(poll :&Poll, conn :&mut Conn) {
+    conn.pending.append(&mut conn.out.buf.lock().unwrap());
+    if conn.pending.len() > MAX_PENDING {
+        conn.pending.clear();
+        conn.closing = true;
+    }
+    while !conn.pending.is_empty() {
+        match conn.stream.write(&conn.pending) {
+            Ok(0) => { conn.pending.clear(); conn.closing = true },
+            Ok(n) => { conn.pending.drain(..n); },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => { conn.pending.clear(); conn.closing = true },
+        }
+    }
+    let interest = if conn.pending.is_empty() {
+        Interest::READABLE
+    } else {
+        Interest::READABLE | Interest::WRITABLE
+    };
+    let _ = poll.registry().reregister(&mut conn.stream, conn.out.token, interest);
+}
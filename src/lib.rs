@@ -0,0 +1,16 @@
+pub mod auction_house;
+pub mod clock;
+pub mod config;
+mod control;
+mod event;
+mod metrics;
+mod pool;
+pub mod rate_limit;
+pub mod server;
+mod session;
+pub mod task;
+
+pub use crate::auction_house::AuctionHouse;
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+pub use crate::config::Config;
+pub use crate::server::{Server, ServerBuilder, ServerHandle};
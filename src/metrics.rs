@@ -0,0 +1,121 @@
+use crate::server::Server;
+use crate::session::COMMANDS;
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    commands :Mutex<HashMap<(&'static str, &'static str), u64>>,
+}
+
+impl Metrics {
+    pub fn command(&self, name :&str, result :&'static str) {
+        let name = COMMANDS.iter().find(|c| **c == name).cloned().unwrap_or("unknown");
+        *self.commands.lock().unwrap().entry((name, result)).or_insert(0) += 1;
+    }
+
+    fn commands(&self) -> Vec<((&'static str, &'static str), u64)> {
+        let mut commands = self.commands.lock().unwrap()
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .collect::<Vec<_>>();
+        commands.sort();
+        commands
+    }
+}
+
+fn header(out :&mut String, name :&str, kind :&str, help :&str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub fn render(server :&Server) -> String {
+    let stats = server.auction_house().stats();
+    let mut out = String::new();
+    header(&mut out, "sd_stock", "gauge", "Droplets in stock by server type.");
+    for (st, n) in stats.stock.iter() {
+        let _ = writeln!(out, "sd_stock{{type=\"{:?}\"}} {}", st, n);
+    }
+    header(&mut out, "sd_reserved_droplets", "gauge", "Reserved droplets by server type and kind.");
+    for (kind, reserved) in [("reserved_d", &stats.reserved_d), ("reserved_a", &stats.reserved_a)].iter() {
+        for (st, n) in reserved.iter() {
+            let _ = writeln!(out, "sd_reserved_droplets{{type=\"{:?}\",kind=\"{}\"}} {}", st, kind, n);
+        }
+    }
+    header(&mut out, "sd_active_auctions", "gauge", "Running timed auctions.");
+    let _ = writeln!(out, "sd_active_auctions {}", stats.auctions);
+    header(&mut out, "sd_queue_length", "gauge", "Bids waiting for stock by server type.");
+    for (st, n) in stats.queues.iter() {
+        let _ = writeln!(out, "sd_queue_length{{type=\"{:?}\"}} {}", st, n);
+    }
+    header(&mut out, "sd_price", "gauge", "Current hourly price of a bought droplet by server type.");
+    for (st, price) in stats.prices.iter() {
+        let _ = writeln!(out, "sd_price{{type=\"{:?}\"}} {}", st, price);
+    }
+    header(&mut out, "sd_clients", "gauge", "Registered clients.");
+    let _ = writeln!(out, "sd_clients {}", stats.clients);
+    header(&mut out, "sd_sessions", "gauge", "Open sessions.");
+    let _ = writeln!(out, "sd_sessions {}", server.sessions().len());
+    header(&mut out, "sd_commands_total", "counter", "Commands handled by name and result.");
+    for ((name, result), n) in server.sessions().metrics().commands() {
+        let _ = writeln!(out, "sd_commands_total{{command=\"{}\",result=\"{}\"}} {}", name, result, n);
+    }
+    header(&mut out, "sd_lock_acquisitions_total", "counter", "Auction house lock acquisitions.");
+    for (name, n, _) in stats.locks.iter() {
+        let _ = writeln!(out, "sd_lock_acquisitions_total{{lock=\"{}\"}} {}", name, n);
+    }
+    header(&mut out, "sd_lock_wait_seconds_total", "counter", "Time spent waiting for auction house locks.");
+    for (name, _, wait) in stats.locks.iter() {
+        let _ = writeln!(out, "sd_lock_wait_seconds_total{{lock=\"{}\"}} {:.9}", name, wait.as_secs_f64());
+    }
+    header(&mut out, "sd_lock_recoveries_total", "counter", "Poisoned auction house locks recovered after a panic.");
+    for (name, n) in stats.recoveries.iter() {
+        let _ = writeln!(out, "sd_lock_recoveries_total{{lock=\"{}\"}} {}", name, n);
+    }
+    header(&mut out, "sd_healthy", "gauge", "1 until a lock has had to be recovered after a panic.");
+    let _ = writeln!(out, "sd_healthy {}", server.auction_house().healthy() as u8);
+    out
+}
+
+pub fn serve(server :Arc<Server>, address :&str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = Arc::clone(&server);
+                    thread::spawn(move || {
+                        if let Err(e) = respond(&server, stream) {
+                            eprintln!("metrics: {:?}", e);
+                        }
+                    });
+                },
+                Err(e) => eprintln!("metrics: {:?}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(server :&Server, mut stream :TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    let mut buf = [0; 1024];
+    let len = stream.read(&mut buf)?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if request.starts_with("GET ") && (path == "/metrics" || path == "/") {
+        ("200 OK", render(server))
+    } else {
+        ("404 Not Found", "Not Found\n".to_string())
+    };
+    write!(stream,
+           "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, body.len(), body)
+}
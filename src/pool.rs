@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// A fixed set of workers, each running one job at a time. Jobs are only taken
+// while a worker is idle, so nothing ever queues behind a busy pool.
+#[derive(Debug)]
+pub struct WorkerPool {
+    sender :Mutex<mpsc::Sender<Job>>,
+    idle :Arc<AtomicUsize>,
+}
+
+impl WorkerPool {
+    pub fn new(size :usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let idle = Arc::new(AtomicUsize::new(size.max(1)));
+        for _ in 0..size.max(1) {
+            let receiver = Arc::clone(&receiver);
+            let idle = Arc::clone(&idle);
+            thread::spawn(move || loop {
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                job();
+                idle.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        WorkerPool {
+            sender: Mutex::new(sender),
+            idle,
+        }
+    }
+
+    // Runs `f` on an idle worker, or drops it and returns false if there is none.
+    pub fn try_execute<F>(&self, f :F) -> bool
+        where
+        F: FnOnce() + Send + 'static
+        {
+            let reserved = self.idle
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            if reserved {
+                self.sender.lock().unwrap()
+                    .send(Box::new(f))
+                    .expect("worker pool has shut down");
+            }
+            reserved
+        }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+// How many account buckets are kept before the full ones are dropped.
+const SWEEP_AT :usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CommandClass {
+    Auth,
+    Read,
+    Trade,
+    Admin,
+}
+
+impl CommandClass {
+    pub fn of(command :&str) -> Self {
+        match command {
+            "register" | "login" => CommandClass::Auth,
+            "buy" | "drop" | "auction" | "start" | "stop" | "reboot" | "renew"
+                | "org-create" | "org-invite" | "org-join" | "org-leave" | "org-remove" | "org-role" => CommandClass::Trade,
+            "stock-add" | "stock-rm" | "clients" | "market" | "suspend" | "unsuspend" | "delete"
+                | "force-drop" | "cancel-auction" | "lockouts" | "unlock" => CommandClass::Admin,
+            _ => CommandClass::Read,
+        }
+    }
+
+    pub fn parse(s :&str) -> Option<Self> {
+        match s {
+            "auth" => Some(CommandClass::Auth),
+            "read" => Some(CommandClass::Read),
+            "trade" => Some(CommandClass::Trade),
+            "admin" => Some(CommandClass::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rate {
+    pub burst :f64,
+    pub per_sec :f64,
+}
+
+impl Rate {
+    pub fn new(burst :f64, per_sec :f64) -> Self {
+        Rate { burst, per_sec }
+    }
+
+    // Room for at least one command, refilled at a rate that is not negative.
+    pub fn valid(&self) -> bool {
+        self.burst.is_finite() && self.per_sec.is_finite() && self.burst >= 1.0 && self.per_sec >= 0.0
+    }
+
+    pub fn defaults() -> HashMap<CommandClass, Rate> {
+        [(CommandClass::Auth, Rate::new(5.0, 0.5)),
+         (CommandClass::Read, Rate::new(20.0, 10.0)),
+         (CommandClass::Trade, Rate::new(10.0, 2.0)),
+         (CommandClass::Admin, Rate::new(50.0, 25.0))]
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens :f64,
+    last :Instant,
+}
+
+impl Bucket {
+    fn new(rate :Rate) -> Self {
+        Bucket { tokens: rate.burst, last: Instant::now() }
+    }
+
+    fn refill(&mut self, rate :Rate) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_sec).min(rate.burst);
+        self.last = now;
+    }
+
+    fn wait_time(&self, rate :Rate) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::from_secs(0)
+        } else if rate.per_sec <= 0.0 {
+            Duration::MAX
+        } else {
+            Duration::try_from_secs_f64((1.0 - self.tokens) / rate.per_sec).unwrap_or(Duration::MAX)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SessionBuckets(HashMap<CommandClass, Bucket>);
+
+// A bucket that has filled back up limits no more than a new one, so those are
+// dropped whenever the map grows to twice its size after the last sweep.
+#[derive(Debug, Default)]
+struct Accounts {
+    buckets :HashMap<(String, CommandClass), Bucket>,
+    sweep_at :usize,
+}
+
+impl Accounts {
+    fn sweep(&mut self, rates :&HashMap<CommandClass, Rate>) {
+        self.buckets.retain(|(_, class), bucket| match rates.get(class) {
+            Some(rate) => { bucket.refill(*rate); bucket.tokens < rate.burst },
+            None => false,
+        });
+        self.sweep_at = (self.buckets.len() * 2).max(SWEEP_AT);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    rates :RwLock<HashMap<CommandClass, Rate>>,
+    accounts :Mutex<Accounts>,
+}
+
+impl RateLimiter {
+    pub fn set_rates(&self, rates :&HashMap<CommandClass, Rate>) {
+        *self.rates.write().unwrap() = rates.clone();
+    }
+
+    pub fn check(
+        &self,
+        session :&mut SessionBuckets,
+        account :Option<&str>,
+        class :CommandClass) -> Result<(), Duration> {
+
+        let rate = match self.rates.read().unwrap().get(&class) {
+            None => return Ok(()),
+            Some(rate) => *rate,
+        };
+        let session_bucket = session.0.entry(class).or_insert_with(|| Bucket::new(rate));
+        session_bucket.refill(rate);
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts.buckets.len() >= accounts.sweep_at.max(SWEEP_AT) {
+            accounts.sweep(&self.rates.read().unwrap());
+        }
+        let mut account_bucket = account.map(|a| {
+            let bucket = accounts.buckets.entry((a.to_owned(), class)).or_insert_with(|| Bucket::new(rate));
+            bucket.refill(rate);
+            bucket
+        });
+        let wait = session_bucket.wait_time(rate)
+            .max(account_bucket.as_ref().map(|b| b.wait_time(rate)).unwrap_or_default());
+        if wait > Duration::from_secs(0) {
+            return Err(wait)
+        }
+        session_bucket.tokens -= 1.0;
+        if let Some(bucket) = account_bucket.as_mut() {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn token_buckets() {
+        let limiter = RateLimiter::default();
+        let mut rates = HashMap::new();
+        rates.insert(CommandClass::Trade, Rate::new(2.0, 0.001));
+        rates.insert(CommandClass::Read, Rate::new(1.0, 20.0));
+        limiter.set_rates(&rates);
+        let (mut first, mut second) = (SessionBuckets::default(), SessionBuckets::default());
+
+        // A burst, then a wait of about the time one token takes to come back.
+        assert!(limiter.check(&mut first, None, CommandClass::Trade).is_ok());
+        assert!(limiter.check(&mut first, None, CommandClass::Trade).is_ok());
+        let wait = limiter.check(&mut first, None, CommandClass::Trade).unwrap_err();
+        assert!(wait > Duration::from_secs(990) && wait <= Duration::from_secs(1000));
+        // Other sessions and classes have buckets of their own; unlisted classes have none.
+        assert!(limiter.check(&mut second, None, CommandClass::Trade).is_ok());
+        assert!(limiter.check(&mut first, None, CommandClass::Read).is_ok());
+        for _ in 0..100 {
+            assert!(limiter.check(&mut first, None, CommandClass::Admin).is_ok());
+        }
+
+        // An account's bucket is shared by all of its sessions.
+        let (mut third, mut fourth) = (SessionBuckets::default(), SessionBuckets::default());
+        assert!(limiter.check(&mut third, Some("a@x"), CommandClass::Trade).is_ok());
+        assert!(limiter.check(&mut fourth, Some("a@x"), CommandClass::Trade).is_ok());
+        assert!(limiter.check(&mut third, Some("a@x"), CommandClass::Trade).is_err());
+        assert!(limiter.check(&mut SessionBuckets::default(), Some("a@x"), CommandClass::Trade).is_err());
+        // The session's own bucket still has a token for another account.
+        assert!(limiter.check(&mut third, Some("b@x"), CommandClass::Trade).is_ok());
+        assert!(limiter.check(&mut third, Some("b@x"), CommandClass::Trade).is_err());
+
+        // Tokens come back over time, up to the burst.
+        assert!(limiter.check(&mut first, None, CommandClass::Read).is_err());
+        thread::sleep(Duration::from_millis(100));
+        assert!(limiter.check(&mut first, None, CommandClass::Read).is_ok());
+        assert!(limiter.check(&mut first, None, CommandClass::Read).is_err());
+    }
+
+    #[test]
+    fn full_account_buckets_are_dropped() {
+        let limiter = RateLimiter::default();
+        let mut rates = HashMap::new();
+        rates.insert(CommandClass::Trade, Rate::new(1.0, 0.0));
+        rates.insert(CommandClass::Read, Rate::new(1.0, 1000.0));
+        limiter.set_rates(&rates);
+        let mut session = SessionBuckets::default();
+
+        assert!(limiter.check(&mut session, Some("a@x"), CommandClass::Trade).is_ok());
+        for n in 1..SWEEP_AT {
+            let _ = limiter.check(&mut session, Some(&format!("{}@y", n)), CommandClass::Read);
+        }
+        thread::sleep(Duration::from_millis(10));
+        let _ = limiter.check(&mut session, Some("b@x"), CommandClass::Read);
+        // Only the bucket that has not filled back up is kept, along with the new one.
+        assert_eq!(limiter.accounts.lock().unwrap().buckets.len(), 2);
+        assert!(limiter.check(&mut SessionBuckets::default(), Some("a@x"), CommandClass::Trade).is_err());
+
+        assert!(!Rate::new(f64::NAN, 1.0).valid());
+        assert!(!Rate::new(5.0, -1.0).valid());
+        assert!(!Rate::new(0.5, 1.0).valid());
+        assert!(Rate::new(1.0, 0.0).valid());
+    }
+}
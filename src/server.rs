@@ -0,0 +1,376 @@
+use crate::auction_house::AuctionHouse;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, ServerMode, ShutdownAuctions};
+use crate::control;
+use crate::event::EventLoops;
+use crate::metrics;
+use crate::pool::WorkerPool;
+use crate::session::{Session, Sessions};
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock, atomic::AtomicBool, atomic::Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+
+const ACCEPT_POLL :Duration = Duration::from_millis(50);
+const CLOSE_GRACE :Duration = Duration::from_secs(1);
+const SERVER_BUSY :&[u8] = b"Server busy, try again later\n";
+// How long a write may wait on a client that is not reading before the connection
+// is dropped, so that notices to it do not hold up everyone else's.
+const WRITE_TIMEOUT :Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub struct Server {
+    ah :Arc<AuctionHouse>,
+    sessions :Arc<Sessions>,
+    pool :Option<WorkerPool>,
+    config :RwLock<Config>,
+    config_path :Option<PathBuf>,
+    draining :Arc<AtomicBool>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    pub fn new(ah :Arc<AuctionHouse>, config :Config, config_path :Option<PathBuf>) -> io::Result<Self> {
+        let sessions = Arc::new(Sessions::new());
+        sessions.limiter().set_rates(&config.rates);
+        ah.set_login_policy(config.login);
+        ah.set_pricing(config.pricing.clone());
+        ah.set_quotas(config.quotas.clone());
+        ah.set_auction_duration(config.auction_duration);
+        ah.set_lease_warning(config.lease_warning);
+        ah.resume_leases();
+        let notify = Arc::clone(&sessions);
+        ah.set_notifier(move |user, msg| notify.notify(user, msg));
+        open_audit_log(&ah, &config)?;
+        Ok(Server {
+            ah,
+            sessions,
+            pool: match config.mode {
+                ServerMode::Threads => Some(WorkerPool::new(config.workers)),
+                ServerMode::Events => None,
+            },
+            config: RwLock::new(config),
+            config_path,
+            draining: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn auction_house(&self) -> &Arc<AuctionHouse> {
+        &self.ah
+    }
+
+    pub(crate) fn sessions(&self) -> &Arc<Sessions> {
+        &self.sessions
+    }
+
+    pub fn config(&self) -> Config {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn run(server :Arc<Server>, listener :TcpListener) -> io::Result<()> {
+        let mut event_loops = match server.pool {
+            Some(_) => None,
+            None => Some(EventLoops::start(Arc::clone(&server), server.config().event_loops)?),
+        };
+        listener.set_nonblocking(true)?;
+        while !server.draining.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((mut stream, peer)) => {
+                    let config = server.config();
+                    if server.sessions.len() >= config.max_connections
+                        || server.sessions.connections_from(peer.ip()) >= config.max_connections_per_ip {
+                        let _ = stream.write_all(SERVER_BUSY);
+                        continue;
+                    }
+                    let result = match event_loops.as_mut() {
+                        Some(event_loops) => event_loops.add(stream, peer),
+                        None => server.spawn_session(stream, peer, &config),
+                    };
+                    if let Err(e) = result {
+                        eprintln!("{:?}", e);
+                    }
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+                Err(e) => eprintln!("{:?}", e),
+            }
+        }
+        drop(listener);
+        server.finish_drain();
+        if let Some(event_loops) = event_loops {
+            event_loops.join();
+        }
+        Ok(())
+    }
+
+    fn spawn_session(&self, stream :TcpStream, peer :SocketAddr, config :&Config) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        if config.idle_timeout > 0 {
+            stream.set_read_timeout(Some(Duration::from_secs(config.idle_timeout)))?;
+        }
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut refuse = stream.try_clone()?;
+        let out = Arc::new(Mutex::new(stream.try_clone()?));
+        let session = Session::new(Arc::clone(&self.ah), Arc::clone(&self.sessions), Some(peer), out);
+        // Every worker is held by a session until it ends, so with none idle the
+        // connection is turned away rather than left waiting for one.
+        if let Some(pool) = self.pool.as_ref() {
+            if !pool.try_execute(move || session.run(stream)) {
+                let _ = refuse.write_all(SERVER_BUSY);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle_signals(&self) -> io::Result<()> {
+        for &signal in [SIGINT, SIGTERM].iter() {
+            // A second signal while draining exits right away.
+            flag::register_conditional_shutdown(signal, 1, Arc::clone(&self.draining))?;
+            flag::register(signal, Arc::clone(&self.draining))?;
+        }
+        Ok(())
+    }
+
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    fn finish_drain(&self) {
+        let config = self.config();
+        self.sessions.begin_shutdown();
+        wait_until(Duration::from_secs(config.drain_timeout), || self.sessions.in_flight() == 0);
+        self.sessions.close_all();
+        wait_until(CLOSE_GRACE, || self.sessions.len() == 0);
+        // Auctions are only saved with the snapshot; without one they would be lost.
+        if config.auctions_on_shutdown == ShutdownAuctions::Settle || config.snapshot.as_os_str().is_empty() {
+            AuctionHouse::settle_auctions(Arc::clone(&self.ah));
+        }
+    }
+
+    pub fn reload(&self) -> io::Result<String> {
+        let path = self.config_path.as_ref()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "server was started without a config file"))?;
+        let new = Config::load(path)?;
+        let mut config = self.config.write().unwrap();
+        let mut notes = Vec::new();
+        if new.listen != config.listen
+            || new.control_socket != config.control_socket
+            || new.metrics_listen != config.metrics_listen {
+            notes.push("listen, control_socket and metrics_listen changes apply on restart".to_string());
+        }
+        if new.mode != config.mode
+            || new.workers != config.workers
+            || new.event_loops != config.event_loops {
+            notes.push("mode, workers and event_loops changes apply on restart".to_string());
+        }
+        for (email, password) in new.admins.iter() {
+            if self.ah.register_admin(email, password).is_ok() {
+                self.ah.audit().record(None, Some(email), "register-admin", &[], "ok");
+                notes.push(format!("registered admin {}", email));
+            }
+        }
+        self.sessions.limiter().set_rates(&new.rates);
+        self.ah.set_login_policy(new.login);
+        self.ah.set_pricing(new.pricing.clone());
+        self.ah.set_quotas(new.quotas.clone());
+        self.ah.set_auction_duration(new.auction_duration);
+        self.ah.set_lease_warning(new.lease_warning);
+        if new.audit_log != config.audit_log
+            || new.audit_max_bytes != config.audit_max_bytes
+            || new.audit_keep != config.audit_keep {
+            open_audit_log(&self.ah, &new)?;
+        }
+        *config = new;
+        Ok(format!("Reloaded {}", path.display())
+           + &notes.iter().map(|n| format!("\n{}", n)).collect::<String>())
+    }
+
+    pub fn snapshot(&self) -> io::Result<PathBuf> {
+        let path = self.config.read().unwrap().snapshot.clone();
+        if path.as_os_str().is_empty() {
+            return Err(io::Error::new(ErrorKind::NotFound, "snapshots are disabled"))
+        }
+        let tmp = path.with_extension("tmp");
+        {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            self.ah.snapshot(&mut out)?;
+            out.flush()?;
+        }
+        fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+}
+
+// Starts a server the way `main` does. Each server is independent: bind it to its
+// own address (port 0 picks a free one) and several can run in one process, as the
+// default config opens nothing else. `Config::standalone` or `Config::load` give
+// it the control socket, metrics, snapshot and audit log as well.
+#[derive(Debug)]
+pub struct ServerBuilder {
+    config :Config,
+    config_path :Option<PathBuf>,
+    clock :Arc<dyn Clock>,
+    signals :bool,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerBuilder {
+            config: Config::default(),
+            config_path: None,
+            clock: Arc::new(SystemClock),
+            signals: false,
+        }
+    }
+}
+
+impl ServerBuilder {
+    pub fn config(mut self, config :Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    // The file `reload` reads; without one reloading is refused.
+    pub fn config_path<P :Into<PathBuf>>(mut self, path :P) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    pub fn listen<A :Into<String>>(mut self, address :A) -> Self {
+        self.config.listen = address.into();
+        self
+    }
+
+    pub fn clock(mut self, clock :Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Drain on SIGINT and SIGTERM. Signals are per process, so only one server should.
+    pub fn handle_signals(mut self, handle :bool) -> Self {
+        self.signals = handle;
+        self
+    }
+
+    pub fn start(self) -> io::Result<ServerHandle> {
+        let config = self.config;
+        let ah = match File::open(&config.snapshot) {
+            Ok(f) => AuctionHouse::restore(BufReader::new(f), self.clock)?,
+            Err(_) => {
+                let ah = AuctionHouse::new(self.clock);
+                for &(st, n) in config.stock.iter() {
+                    for _ in 0..n { ah.add(st); }
+                }
+                ah
+            },
+        };
+        for (email, password) in config.admins.iter() {
+            let _ = ah.register_admin(email, password);
+        }
+        let listener = TcpListener::bind(&config.listen)?;
+        let local_addr = listener.local_addr()?;
+        let control_socket = config.control_socket.clone();
+        let metrics_listen = config.metrics_listen.clone();
+        let server = Arc::new(Server::new(ah, config, self.config_path)?);
+        if self.signals {
+            server.handle_signals()?;
+        }
+        if !control_socket.as_os_str().is_empty() {
+            control::listen(Arc::clone(&server), &control_socket)?;
+        }
+        if !metrics_listen.is_empty() {
+            metrics::serve(Arc::clone(&server), &metrics_listen)?;
+        }
+        let running = Arc::clone(&server);
+        let thread = thread::spawn(move || {
+            Server::run(Arc::clone(&running), listener)?;
+            if !running.config().snapshot.as_os_str().is_empty() {
+                running.snapshot()?;
+            }
+            if !control_socket.as_os_str().is_empty() {
+                let _ = fs::remove_file(&control_socket);
+            }
+            Ok(())
+        });
+        Ok(ServerHandle { server, local_addr, thread: Some(thread) })
+    }
+}
+
+// A running server. Dropping the handle shuts the server down.
+#[derive(Debug)]
+pub struct ServerHandle {
+    server :Arc<Server>,
+    local_addr :SocketAddr,
+    thread :Option<JoinHandle<io::Result<()>>>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn server(&self) -> &Arc<Server> {
+        &self.server
+    }
+
+    pub fn auction_house(&self) -> &Arc<AuctionHouse> {
+        self.server.auction_house()
+    }
+
+    // Drains as on SIGTERM and waits until the server has stopped.
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.server.drain();
+        self.join()
+    }
+
+    // Waits for the server to stop after a signal or a `drain` on the control socket.
+    pub fn wait(mut self) -> io::Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> io::Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread.join()
+                .unwrap_or_else(|_| Err(io::Error::other("server thread panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.server.drain();
+            let _ = self.join();
+        }
+    }
+}
+
+fn wait_until<F :Fn() -> bool>(timeout :Duration, done :F) {
+    let start = Instant::now();
+    while !done() && start.elapsed() < timeout {
+        thread::sleep(ACCEPT_POLL);
+    }
+}
+
+fn open_audit_log(ah :&AuctionHouse, config :&Config) -> io::Result<()> {
+    if config.audit_log.as_os_str().is_empty() {
+        ah.audit().close();
+        Ok(())
+    } else {
+        ah.audit().open(&config.audit_log, config.audit_max_bytes, config.audit_keep)
+    }
+}
@@ -1,29 +1,110 @@
-use crate::auction_house::{AuctionHouse, AHouseError, bid::Bid, server_type::ServerType, client::Client};
+use crate::auction_house::{AuctionHouse, AHouseError, Power, bid::Bid, server_type::ServerType, client::Client};
+use crate::auction_house::droplet::DropletId;
+use crate::auction_house::history::{Outcome, PriceStats};
+use crate::auction_house::org::{Org, Role};
+use crate::auction_house::quota::{self, Quota};
+use crate::config::parse_length;
+use crate::metrics::Metrics;
+use crate::rate_limit::{CommandClass, RateLimiter, SessionBuckets};
 
-use std::io::{Read, Write};
+use chrono::{DateTime, Duration, Local};
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, ErrorKind, Read, Write};
 use std::str::FromStr;
-use std::net::TcpStream;
-use std::sync::{Arc, atomic::AtomicUsize, atomic::Ordering};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex, RwLock, atomic::AtomicBool, atomic::AtomicUsize, atomic::Ordering};
 use std::ops::Add;
+use std::panic::{self, AssertUnwindSafe};
+
+pub const COMMANDS :&[&str] = &[
+    "register", "login", "ls", "buy", "profile", "drop", "auction", "quit",
+    "start", "stop", "reboot", "proto", "prices", "history", "renew", "quota",
+    "orgs", "org", "org-create", "org-invite", "org-join", "org-leave", "org-remove", "org-role",
+    "stock-add", "stock-rm", "clients", "market", "suspend", "unsuspend", "delete",
+    "force-drop", "cancel-auction", "lockouts", "unlock",
+];
+
+const AUDITED :&[&str] = &[
+    "register", "login", "buy", "drop", "auction", "start", "stop", "reboot", "renew",
+    "org-create", "org-invite", "org-join", "org-leave", "org-remove", "org-role",
+    "stock-add", "stock-rm", "suspend", "unsuspend", "delete",
+    "force-drop", "cancel-auction", "unlock",
+];
+
+// Closed auctions listed by `history`; the statistics cover the whole window.
+const HISTORY_ROWS :usize = 20;
 
 const LOGIN_REQUIRED :&str = "You must be logged in to use this!";
-const ID :AtomicUsize = AtomicUsize::new(0);
+const SHUTTING_DOWN :&str = "Server is shutting down";
+const INTERNAL_ERROR :&str = "500: Internal Server Error";
+pub const NOTICE :&str = "Notice: ";
+pub const IDLE_TIMEOUT :&str = "Idle timeout, closing connection";
+static ID :AtomicUsize = AtomicUsize::new(0);
 
 pub struct Session {
     id :usize,
+    peer :Option<SocketAddr>,
     user :Option<String>,
     ah :Arc<AuctionHouse>,
-    stream: TcpStream,
+    sessions :Arc<Sessions>,
+    out :Arc<dyn Output>,
+    buckets :SessionBuckets,
+    framed :bool,
+}
+
+pub trait Output: Send + Sync + Debug {
+    fn send(&self, msg :&str) -> io::Result<()>;
+    fn close(&self);
+}
+
+impl Output for Mutex<TcpStream> {
+    // A write that failed or timed out may have sent part of a line, so the
+    // connection is shut down and later sends fail right away.
+    fn send(&self, msg :&str) -> io::Result<()> {
+        let mut stream = self.lock().unwrap();
+        let sent = stream.write_all(msg.to_owned().add("\n").as_bytes());
+        if sent.is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        sent
+    }
+
+    fn close(&self) {
+        let _ = self.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id :usize,
+    pub peer :Option<SocketAddr>,
+    pub user :Option<String>,
+    pub since :DateTime<Local>,
+    out :Arc<dyn Output>,
+}
+
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions :RwLock<HashMap<usize, SessionInfo>>,
+    closing :AtomicBool,
+    in_flight :AtomicUsize,
+    limiter :RateLimiter,
+    metrics :Metrics,
 }
 
 enum Command {
     Register(Client),
     Login(Client),
     Ls(String),
-    Buy(u32),
-    Auction,
+    Buy(DropletId, i32),
+    Auction(String),
     Profile(String),
     DropServer,
+    Power(String),
+    Org(String),
+    Admin(String),
 }
 
 struct CommandError(String);
@@ -31,10 +112,36 @@ struct CommandError(String);
 impl From<AHouseError> for CommandError {
     fn from(e :AHouseError) -> Self {
         match e {
-            AHouseError::OutOfStock(_) => CommandError("Out of stock".into()),
+            AHouseError::OutOfStock(st) => CommandError(format!("Out of stock: {:?}", st)),
             AHouseError::EmailTaken(e) => CommandError("Email Taken: ".to_owned() + &e),
-            AHouseError::LockError(_) => CommandError("500: Internal Server Error".into()),
-            _ => unreachable!(),
+            AHouseError::LockError(e) => {
+                eprintln!("lock error: {}", e);
+                CommandError(INTERNAL_ERROR.into())
+            },
+            AHouseError::InvalidClient(e) => CommandError("Invalid client: ".to_owned() + &e),
+            AHouseError::PermissionDenied(e) => CommandError("Permission denied for ".to_owned() + &e),
+            AHouseError::Suspended(e) => CommandError("Account suspended: ".to_owned() + &e),
+            AHouseError::InvalidDroplet(id) => CommandError(format!("Invalid Server id: {}", id)),
+            AHouseError::InvalidState(id, state) =>
+                CommandError(format!("Not allowed while server {} is {}", id, state.name())),
+            AHouseError::NoAuction(st) => CommandError(format!("No auction running for {:?}", st)),
+            AHouseError::NoLease(id) => CommandError(format!("No lease on server {}", id)),
+            AHouseError::QuotaExceeded(quota) => CommandError(format!("Quota exceeded: {}", match quota {
+                Quota::Droplets(st, n) => format!("at most {} {:?} droplets per client", n, st),
+                Quota::Spend(limit, period) =>
+                    format!("spend is limited to {:.2} per {}", limit, quota::length(period)),
+                Quota::Wins(n, window) => format!("at most {} auction wins per {}", n, quota::length(window)),
+            })),
+            AHouseError::NoOrg(org) => CommandError(format!("No such organization: {}", org)),
+            AHouseError::OrgTaken(org) => CommandError(format!("Organization name taken: {}", org)),
+            AHouseError::NotMember(org, email) => CommandError(format!("Not a member of {}: {}", org, email)),
+            AHouseError::AlreadyMember(org, email) => CommandError(format!("Already a member of {}: {}", org, email)),
+            AHouseError::NoInvite(org) => CommandError(format!("No invite to {}", org)),
+            AHouseError::LastOwner(org) => CommandError(format!("Organization needs another owner first: {}", org)),
+            AHouseError::TooManyAttempts(wait) =>
+                CommandError(format!("Too many failed logins, retry in {}s", wait.as_secs() + 1)),
+            AHouseError::BidTooLow(top) => CommandError(format!("Bid too low, top bid is {}", top)),
+            AHouseError::InvalidBid(amount) => CommandError(format!("Bid must be positive: {}", amount)),
         }
     }
 }
@@ -59,94 +166,282 @@ impl std::fmt::Display for CommandError {
 
 type CommandResult = Result<Command, CommandError>;
 
+impl Sessions {
+    pub fn new() -> Self {
+        Sessions::default()
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut list = self.sessions.read().unwrap().values().cloned().collect::<Vec<_>>();
+        list.sort_by_key(|s| s.id);
+        list
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.read().unwrap().len()
+    }
+
+    pub fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn connections_from(&self, ip :IpAddr) -> usize {
+        self.sessions.read().unwrap()
+            .values()
+            .filter(|s| s.peer.map(|p| p.ip()) == Some(ip))
+            .count()
+    }
+
+    // Taken out of the map first so that no write is made under the lock.
+    fn outputs<F :Fn(&SessionInfo) -> bool>(&self, f :F) -> Vec<Arc<dyn Output>> {
+        self.sessions.read().unwrap()
+            .values()
+            .filter(|s| f(s))
+            .map(|s| Arc::clone(&s.out))
+            .collect()
+    }
+
+    pub fn broadcast(&self, msg :&str) {
+        for out in self.outputs(|_| true) {
+            let _ = out.send(msg);
+        }
+    }
+
+    pub fn notify(&self, user :&str, msg :&str) {
+        let msg = format!("{}{}", NOTICE, msg);
+        for out in self.outputs(|s| s.user.as_deref() == Some(user)) {
+            let _ = out.send(&msg);
+        }
+    }
+
+    pub fn begin_shutdown(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+        self.broadcast(SHUTTING_DOWN);
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn close_all(&self) {
+        for out in self.outputs(|_| true) {
+            out.close();
+        }
+    }
+
+    fn set_user(&self, id :usize, user :&str) {
+        if let Some(s) = self.sessions.write().unwrap().get_mut(&id) {
+            s.user = Some(user.to_owned());
+        }
+    }
+}
+
 impl Session {
-    pub fn new(ah :Arc<AuctionHouse>, stream :TcpStream) -> Self {
+    pub fn new(
+        ah :Arc<AuctionHouse>,
+        sessions :Arc<Sessions>,
+        peer :Option<SocketAddr>,
+        out :Arc<dyn Output>) -> Self {
+
+        let id = ID.fetch_add(1, Ordering::SeqCst);
+        sessions.sessions.write().unwrap().insert(id, SessionInfo {
+            id,
+            peer,
+            user: None,
+            since: Local::now(),
+            out: Arc::clone(&out),
+        });
         Session {
-            id: ID.fetch_add(1, Ordering::SeqCst),
+            id,
+            peer,
             user: None,
             ah,
-            stream,
+            sessions,
+            out,
+            buckets: SessionBuckets::default(),
+            framed: false,
         }
-
     }
 
-    pub fn run(mut self) {
+    pub fn run(mut self, mut stream :TcpStream) {
         let mut buf = [0; 1024];
         loop {
-            let len = match self.stream.read(&mut buf) {
+            let len = match stream.read(&mut buf) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    let _ = self.out.send(IDLE_TIMEOUT);
+                    break
+                },
                 Err(_) => break,
                 Ok(0) => break,
                 Ok(n) => n,
             };
-            let input = String::from_utf8_lossy(&buf[..len]);
-            let command = input
-                .split(' ')
-                .map(|s| s.trim())
-                .filter(|s| s.len() > 0)
-                .collect::<Vec<&str>>();
-            if command.len() == 0 { continue }
-            if command[0] == "quit" { break }
-            let response = self.run_command(&command);
-            if let Err(_) = self.stream.write_all(response.add("\n").as_bytes()) {
+            if !self.handle_input(&String::from_utf8_lossy(&buf[..len])) {
                 break
             }
         }
     }
 
-    fn run_command(&mut self, command :&[&str]) -> String {
-        match command[0] {
+    pub fn handle_input(&mut self, input :&str) -> bool {
+        let command = input
+            .split(' ')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<&str>>();
+        if command.is_empty() { return true }
+        if command[0] == "quit" { return false }
+        self.sessions.in_flight.fetch_add(1, Ordering::SeqCst);
+        let throttled = self.sessions.limiter.check(
+            &mut self.buckets,
+            self.user.as_deref(),
+            CommandClass::of(command[0]));
+        let (response, result) = if self.sessions.is_closing() {
+            (SHUTTING_DOWN.to_owned(), "refused")
+        } else if let Err(wait) = throttled {
+            (format!("Too many requests, retry in {:.1}s", wait.as_secs_f64()), "throttled")
+        } else {
+            // A panicking command must not take the other sessions on this thread
+            // down with it; the locks it held are recovered by the next user.
+            match panic::catch_unwind(AssertUnwindSafe(|| self.run_command(&command))) {
+                Ok(result) => {
+                    self.audit(&command, &result);
+                    match result {
+                        Ok(response) => (response, "ok"),
+                        Err(e) => (e.to_string(), "error"),
+                    }
+                },
+                Err(_) => {
+                    self.audit(&command, &Err(CommandError(INTERNAL_ERROR.into())));
+                    (INTERNAL_ERROR.to_owned(), "panic")
+                },
+            }
+        };
+        self.sessions.metrics.command(command[0], result);
+        self.sessions.in_flight.fetch_sub(1, Ordering::SeqCst);
+        if self.framed {
+            self.out.send(&frame(result, &response)).is_ok()
+        } else {
+            self.out.send(&response).is_ok()
+        }
+    }
+
+    fn audit(&self, command :&[&str], result :&Result<String, CommandError>) {
+        if !AUDITED.contains(&command[0]) { return }
+        let (account, params) = match command[0] {
+            // Never write passwords to the audit log.
+            "register" | "login" => (command.get(1).cloned(), &command[1..command.len().min(2)]),
+            _ => (self.user.as_deref(), &command[1..]),
+        };
+        let outcome = match result {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        self.ah.audit().record(Some(self.id), account, command[0], params, &outcome);
+    }
+
+    fn run_command(&mut self, command :&[&str]) -> Result<String, CommandError> {
+        Ok(match command[0] {
             "register" => {
-                match self.register(&command[1..]) {
-                    Err(e) => format!("{}", e),
-                    Ok(_) => {
-                        self.user = Some(command[1].to_owned());
-                        "Registered successfully!".into()
-                    },
+                if let Command::Register(c) = self.register(&command[1..])? {
+                    self.user = Some(c.email().to_owned());
+                    self.sessions.set_user(self.id, c.email());
                 }
+                "Registered successfully!".into()
             }
             "login" => {
-                match self.login(&command[1..]) {
-                    Err(e) => format!("{}", e),
-                    Ok(_) => {
-                        self.user = Some(command[1].to_owned());
-                        "Logged in successfully!".into()
-                    },
+                if let Command::Login(c) = self.login(&command[1..])? {
+                    self.user = Some(c.email().to_owned());
+                    self.sessions.set_user(self.id, c.email());
                 }
+                "Logged in successfully!".into()
             }
             "ls" => {
-                match self.ls(&command[1..]) {
-                    Err(e) => format!("{}", e),
-                    Ok(Command::Ls(s)) => s,
-                    Ok(_) => "".into(),
+                match self.ls(&command[1..])? {
+                    Command::Ls(s) => s,
+                    _ => "".into(),
+                }
+            }
+            "prices" => {
+                match self.prices(&command[1..])? {
+                    Command::Ls(s) => s,
+                    _ => unreachable!(),
+                }
+            }
+            "history" => {
+                match self.history(&command[1..])? {
+                    Command::Ls(s) => s,
+                    _ => unreachable!(),
+                }
+            }
+            "quota" => {
+                match self.quota(&command[1..])? {
+                    Command::Ls(s) => s,
+                    _ => unreachable!(),
                 }
             }
             "buy" => {
-                match self.buy(&command[1..]) {
-                    Err(e) => format!("{}", e),
-                    Ok(_) => "Purchase successfull!".into(),
+                match self.buy(&command[1..])? {
+                    Command::Buy(id, price) => format!("Purchase successfull! Droplet id: {} at {}/h", id, price),
+                    _ => unreachable!(),
                 }
             }
             "profile" => {
-                match self.profile() {
-                    Err(e) => format!("{}", e),
-                    Ok(Command::Profile(s)) => s,
-                    Ok(_) => unreachable!(),
+                match self.profile()? {
+                    Command::Profile(s) => s,
+                    _ => unreachable!(),
                 }
             }
             "drop" => {
-                match self.drop_server(&command[1..]) {
-                    Err(e) => format!("{}", e),
-                    Ok(_) => "Server removed successfully".into(),
-                }
+                self.drop_server(&command[1..])?;
+                "Server removed successfully".into()
             }
+            "renew" => {
+                match self.renew(&command[1..])? {
+                    Command::Power(s) => s,
+                    _ => unreachable!(),
+                }
+            },
+            "start" | "stop" | "reboot" => {
+                match self.power(command[0], &command[1..])? {
+                    Command::Power(s) => s,
+                    _ => unreachable!(),
+                }
+            },
+            "proto" => {
+                match command.get(1) {
+                    Some(&"framed") => { self.framed = true; "Framed replies enabled".into() },
+                    Some(&"plain") => { self.framed = false; "Plain replies enabled".into() },
+                    _ => Err("Usage: proto <framed|plain>")?,
+                }
+            },
             "auction" => {
-                match self.auction(&command[1..]) {
-                    Err(e) => format!("{}", e),
-                    Ok(_) => "Auction Started".into(),
+                match self.auction(&command[1..])? {
+                    Command::Auction(s) => s,
+                    _ => unreachable!(),
                 }
             },
-            s => format!("Command not found: {}", s),
-        }
+            "orgs" | "org" | "org-create" | "org-invite" | "org-join" | "org-leave" | "org-remove" | "org-role" => {
+                match self.org(command[0], &command[1..])? {
+                    Command::Org(s) => s,
+                    _ => unreachable!(),
+                }
+            },
+            "stock-add" | "stock-rm" | "clients" | "market" | "suspend" | "unsuspend" | "delete"
+                | "force-drop" | "cancel-auction" | "lockouts" | "unlock" => {
+                match self.admin(command[0], &command[1..])? {
+                    Command::Admin(s) => s,
+                    _ => unreachable!(),
+                }
+            },
+            s => Err(format!("Command not found: {}", s))?,
+        })
     }
 
     fn register(&self, args :&[&str]) -> CommandResult {
@@ -161,47 +456,133 @@ impl Session {
         if args.len() < 2 {
             Err("Usage: login <email> <password>")?
         } else {
-            self.ah.login(args[0], args[1]).map(|c| Ok(Command::Login(c)))?
+            self.ah.login(args[0], args[1], self.peer.map(|p| p.ip())).map(|c| Ok(Command::Login(c)))?
         }
     }
 
     fn ls(&self, args :&[&str]) -> CommandResult {
-        if args.len() == 0 {
+        if args.is_empty() {
             let stock = self.ah.ls();
-            let mut result = String::from_str("Type\tAmount in stock\n=======================\n")
+            let mut result = String::from_str("Type\tAmount in stock\tPrice\n=============================\n")
                 .unwrap();
-            for (k, v) in stock.iter() {
-                result += &format!("{:?}\t{}\n", k, v);
+            for (k, v, price) in stock.iter() {
+                result += &format!("{:?}\t{}\t{}\n", k, v, price);
             }
             Ok(Command::Ls(result))
         } else if args[0] == "-m" {
-            if self.user.is_none() {
-                Err(LOGIN_REQUIRED)?
-            } else {
-                Ok(Command::Ls("ID\tType\n=========================\n".to_string()
-                               + &self.ah.ls_m(self.user.as_ref().unwrap())
-                               .iter()
-                               .map(|d| format!("{}\t{:?}\n", d.id(), d.server_type()))
-                               .fold(String::new(), |x, acc| acc + &x)
-                              ))
+            match self.user.as_ref() {
+                None => Err(LOGIN_REQUIRED)?,
+                Some(user) => {
+                    let now = self.ah.now();
+                    Ok(Command::Ls("ID\tType\tState\tUptime\tExpires\tOwner\n=================================\n".to_string()
+                                   + &self.ah.ls_m(user)
+                                   .iter()
+                                   .map(|d| format!("{}\t{:?}\t{}\t{}\t{}\t{}\n",
+                                                    d.id(),
+                                                    d.server_type(),
+                                                    d.state(now).name(),
+                                                    d.uptime(now).map(uptime).unwrap_or_else(|| "-".into()),
+                                                    d.expires()
+                                                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                                                    .unwrap_or_else(|| "-".into()),
+                                                    d.owner()))
+                                   .collect::<String>()))
+                },
             }
         } else {
-            Err("Usage: ls [-m]\n\t-m show my droplets")?
+            Err("Usage: ls [-m]\n\t-m show my droplets and those of my organizations")?
         }
     }
 
+    // Upcoming scheduled price changes, so batch work can wait for a cheap window.
+    fn prices(&self, args :&[&str]) -> CommandResult {
+        let only = match args.first() {
+            None => None,
+            Some(st) => match ServerType::parse(st) {
+                None => Err("Usage: prices [Fast,Slow]")?,
+                st => st,
+            },
+        };
+        let prices = self.ah.prices();
+        let now = self.ah.now();
+        Ok(Command::Ls("Type\tFrom\tRate\tPrice\n=============================\n".to_string()
+                       + &prices
+                       .iter()
+                       .filter(|(st, _, _, _)| only.is_none_or(|o| o == *st))
+                       .map(|(st, at, percent, price)| format!(
+                               "{:?}\t{}\t{}%\t{}\n",
+                               st,
+                               if *at <= now { "now".to_string() } else { at.format("%a %Y-%m-%d %H:%M").to_string() },
+                               percent,
+                               price))
+                       .collect::<String>()))
+    }
+
+    fn history(&self, args :&[&str]) -> CommandResult {
+        const USAGE :&str = "Usage: history <Fast,Slow> [window]\n\twindow like 30m, 24h or 7d, 24h by default";
+        let (st, window) = match args {
+            [st] => (ServerType::parse(st), Some(Duration::hours(24))),
+            [st, window] => (ServerType::parse(st), parse_length(window)),
+            _ => Err(USAGE)?,
+        };
+        let (st, window) = match (st, window) {
+            (Some(st), Some(window)) => (st, window),
+            _ => Err(USAGE)?,
+        };
+        let records = self.ah.history(st, self.ah.now() - window);
+        let count = |outcome| records.iter().filter(|r| r.outcome == outcome).count();
+        let mut result = format!("{:?} auctions in the last {}: {} sold, {} unsold, {} cancelled\n",
+                                 st, args.get(1).unwrap_or(&"24h"),
+                                 count(Outcome::Sold), count(Outcome::Unsold), count(Outcome::Cancelled));
+        result += &match PriceStats::of(&records) {
+            Some(s) => format!("Clearing price: min {}, max {}, mean {:.2}, median {}\n", s.min, s.max, s.mean, s.median),
+            None => "Clearing price: nothing sold\n".to_string(),
+        };
+        result += "Closed\tResult\tTop bidder\tPrice\tBids\tLength\n=============================================\n";
+        for r in records.iter().take(HISTORY_ROWS) {
+            result += &format!("{}\t{}\t{}\t{}\t{}\t{}\n",
+                               r.closed.format("%Y-%m-%d %H:%M:%S"), r.outcome.name(), r.bidder,
+                               r.price, r.bids, uptime(r.closed - r.started));
+        }
+        Ok(Command::Ls(result))
+    }
+
+    fn quota(&self, args :&[&str]) -> CommandResult {
+        let user = match self.user.as_ref() {
+            None => Err(LOGIN_REQUIRED)?,
+            Some(user) => user,
+        };
+        let org = match org_arg(args) {
+            (org, []) => org,
+            _ => Err("Usage: quota [-o org]")?,
+        };
+        Ok(Command::Ls("Quota\tUsed\tLimit\tLeft\n=============================\n".to_string()
+                       + &self.ah.quota(user, org)?
+                       .iter()
+                       .map(|(quota, used, left)| format!("{}\t{}\t{}\t{}\n",
+                                                          quota.name(),
+                                                          quota.amount(*used),
+                                                          quota.amount(quota.limit()),
+                                                          quota.amount(*left)))
+                       .collect::<String>()))
+    }
+
     fn buy(&self, args :&[&str]) -> CommandResult {
-        if self.user.is_none() {
-            Err(LOGIN_REQUIRED)?
-        } else if args.len() < 1 {
-            Err("Usage: buy <Fast,Slow>")?
+        let user = match self.user.as_ref() {
+            None => Err(LOGIN_REQUIRED)?,
+            Some(user) => user,
+        };
+        let (org, args) = org_arg(args);
+        if args.is_empty() || args.len() > 2 {
+            Err("Usage: buy [-o org] <Fast,Slow> [lease]\n\t-o buy for an organization\n\tlease like 30m, 24h or 7d, kept until dropped by default")?
         } else {
-            let st = match ServerType::from_str(args[0]) {
+            let st = match ServerType::parse(args[0]) {
                 None => Err("Invalid server type!")?,
                 Some(s) => s,
             };
-            AuctionHouse::buy(Arc::clone(&self.ah), st, &self.user.as_ref().unwrap())
-                .map(|id| Command::Buy(id))
+            let lease = args.get(1).map(|l| parse_length(l).ok_or("Invalid lease")).transpose()?;
+            AuctionHouse::buy(Arc::clone(&self.ah), st, user, org, lease)
+                .map(|(id, price)| Command::Buy(id, price))
                 .map_err(|e| e.into())
         }
     }
@@ -210,43 +591,272 @@ impl Session {
         match self.user.as_ref() {
             None => Err(LOGIN_REQUIRED)?,
             Some(ctl) => {
-                let c = self.ah.profile(&ctl).unwrap();
-                Ok(Command::Profile(format!("email: {}", c.email())))
+                let c = self.ah.profile(ctl).ok_or_else(|| AHouseError::InvalidClient(ctl.clone()))?;
+                Ok(Command::Profile(format!("email: {}\ncharges: {:.2}", c.email(), self.ah.charges(ctl))))
             }
         }
     }
 
     fn drop_server(&self, args :&[&str]) -> CommandResult {
         if self.user.is_none() { Err(LOGIN_REQUIRED)? }
-        if args.len() < 1 { Err("Usage: drop <id>")? }
-        args[0].parse::<u32>()
-            .map_err(|_| "Invalid id: {}".to_owned() + args[0])
+        if args.is_empty() { Err("Usage: drop <id>")? }
+        droplet_id(args[0])
             .and_then(|id|
                       if self.ah.drop_server(self.user.as_ref().unwrap(), id) {
                           Ok(Command::DropServer)
                       } else {
-                          Err("Invalid Server id".to_owned())
+                          Err("Invalid Server id".into())
                       }
                      )
-            .map_err(|s| s.into())
+    }
+
+    fn power(&self, command :&str, args :&[&str]) -> CommandResult {
+        let user = match self.user.as_ref() {
+            None => Err(LOGIN_REQUIRED)?,
+            Some(user) => user,
+        };
+        if args.is_empty() { Err(format!("Usage: {} <id>", command))? }
+        let id = droplet_id(args[0])?;
+        let action = match command {
+            "start" => Power::Start,
+            "stop" => Power::Stop,
+            _ => Power::Reboot,
+        };
+        let state = self.ah.power(user, id, action)?;
+        Ok(Command::Power(format!("Server {} is {}", id, state.name())))
+    }
+
+    fn renew(&self, args :&[&str]) -> CommandResult {
+        let user = match self.user.as_ref() {
+            None => Err(LOGIN_REQUIRED)?,
+            Some(user) => user,
+        };
+        if args.len() != 2 { Err("Usage: renew <id> <length>\n\tlength like 30m, 24h or 7d")? }
+        let id = droplet_id(args[0])?;
+        let by = parse_length(args[1]).ok_or("Invalid length")?;
+        let expires = self.ah.renew(user, id, by)?;
+        Ok(Command::Power(format!("Lease on server {} renewed until {}", id, expires.format("%Y-%m-%d %H:%M:%S"))))
     }
 
     fn auction(&self, args :&[&str]) -> CommandResult {
         if self.user.is_none() { Err(LOGIN_REQUIRED)? };
-        if args.len() < 2 { Err("Usage: start-bid <Fast|Slow> <amount>")? };
-        let sv_tp = match ServerType::from_str(args[0]) {
+        let (org, args) = org_arg(args);
+        if args.len() < 2 || args.len() > 3 { Err("Usage: auction [-o org] <Fast|Slow> <amount> [lease]")? };
+        let sv_tp = match ServerType::parse(args[0]) {
             None => Err("Invalid server type!")?,
             Some(sv_tp) => sv_tp,
         };
+        let lease = args.get(2).map(|l| parse_length(l).ok_or("Invalid lease")).transpose()?;
         args[1].parse::<i32>()
             .map_err(|_| CommandError("Invalid amount".into()))
             .and_then(|amount|
                       AuctionHouse::auction(
                           Arc::clone(&self.ah),
                           sv_tp,
-                          Bid::new(self.user.as_ref().unwrap(), amount),
-                          self.id
-                          )
-                      .map(|_| Command::Auction).map_err(|e| e.into()))
+                          Bid::new(self.user.as_ref().unwrap(), amount).with_lease(lease).with_org(org))
+                      .map(|kind| Command::Auction(kind.message(sv_tp))).map_err(|e| e.into()))
+    }
+
+    fn org(&self, command :&str, args :&[&str]) -> CommandResult {
+        let user = match self.user.as_ref() {
+            None => Err(LOGIN_REQUIRED)?,
+            Some(user) => user,
+        };
+        let role = |r :&str| Role::parse(r).ok_or_else(|| CommandError::from("Invalid role, use owner, purchaser or viewer"));
+        Ok(Command::Org(match (command, args) {
+            ("orgs", []) => {
+                "Organization\tRole\tStatus\n=============================\n".to_string()
+                    + &self.ah.orgs(user)
+                    .iter()
+                    .map(|(name, role, invited)| format!("{}\t{}\t{}\n",
+                                                         name, role.name(), if *invited { "invited" } else { "member" }))
+                    .collect::<String>()
+            },
+            ("org", [name]) => {
+                let org = self.ah.org(user, name)?;
+                format!("{}: {} droplets, charges {:.2}\n", name, org.droplets, org.charges)
+                    + "Member\tRole\tStatus\n=============================\n"
+                    + &org.members
+                    .iter()
+                    .map(|(email, role, invited)| format!("{}\t{}\t{}\n",
+                                                          email, role.name(), if *invited { "invited" } else { "member" }))
+                    .collect::<String>()
+            },
+            ("org-create", [name]) => {
+                if !Org::valid_name(name) {
+                    Err("Invalid organization name, use up to 32 letters, digits, - or _")?
+                }
+                self.ah.create_org(user, name)?;
+                format!("Organization {} created", name)
+            },
+            ("org-invite", [name, email, r]) => {
+                let r = role(r)?;
+                self.ah.invite(user, name, email, r)?;
+                format!("Invited {} to {} as {}", email, name, r.name())
+            },
+            ("org-join", [name]) => {
+                let r = self.ah.join_org(user, name)?;
+                format!("Joined {} as {}", name, r.name())
+            },
+            ("org-leave", [name]) => {
+                self.ah.remove_member(user, name, user)?;
+                format!("Left {}", name)
+            },
+            ("org-remove", [name, email]) => {
+                self.ah.remove_member(user, name, email)?;
+                format!("Removed {} from {}", email, name)
+            },
+            ("org-role", [name, email, r]) => {
+                let r = role(r)?;
+                self.ah.set_role(user, name, email, r)?;
+                format!("{} is now {} in {}", email, r.name(), name)
+            },
+            ("orgs", _) => Err("Usage: orgs")?,
+            ("org", _) => Err("Usage: org <name>")?,
+            ("org-create", _) => Err("Usage: org-create <name>")?,
+            ("org-invite", _) => Err("Usage: org-invite <name> <email> <owner|purchaser|viewer>")?,
+            ("org-join", _) => Err("Usage: org-join <name>")?,
+            ("org-leave", _) => Err("Usage: org-leave <name>")?,
+            ("org-remove", _) => Err("Usage: org-remove <name> <email>")?,
+            ("org-role", _) => Err("Usage: org-role <name> <email> <owner|purchaser|viewer>")?,
+            (s, _) => Err(format!("Command not found: {}", s))?,
+        }))
     }
+
+    fn admin(&self, command :&str, args :&[&str]) -> CommandResult {
+        let admin = match self.user.as_ref() {
+            None => Err(LOGIN_REQUIRED)?,
+            Some(admin) => admin,
+        };
+        match command {
+            "stock-add" | "stock-rm" => {
+                if args.is_empty() { Err(format!("Usage: {} <Fast|Slow> [amount]", command))? }
+                let sv_tp = match ServerType::parse(args[0]) {
+                    None => Err("Invalid server type!")?,
+                    Some(sv_tp) => sv_tp,
+                };
+                let amount = match args.get(1).map(|a| a.parse::<u32>()) {
+                    None => 1,
+                    Some(Ok(amount)) => amount,
+                    Some(Err(_)) => Err("Invalid amount")?,
+                };
+                let left = if command == "stock-add" {
+                    self.ah.add_stock(admin, sv_tp, amount)?
+                } else {
+                    self.ah.remove_stock(admin, sv_tp, amount)?
+                };
+                Ok(Command::Admin(format!("{:?} in stock: {}", sv_tp, left)))
+            },
+            "clients" => {
+                Ok(Command::Admin("Email\tRole\tStatus\tDroplets\tLogin\n=========================\n".to_string()
+                                  + &self.ah.clients(admin)?
+                                  .iter()
+                                  .map(|(c, n, l)| format!("{}\t{}\t{}\t{}\t{}\n",
+                                                           c.email(),
+                                                           if c.is_admin() { "admin" } else { "user" },
+                                                           if c.is_suspended() { "suspended" } else { "active" },
+                                                           n,
+                                                           match l {
+                                                               Some(l) if l.locked => format!("locked {}s", l.retry_in.as_secs()),
+                                                               Some(l) => format!("{} failures", l.failures),
+                                                               None => "ok".into(),
+                                                           }))
+                                  .collect::<String>()
+                                 ))
+            },
+            "market" => {
+                Ok(Command::Admin("Type\tStock\tHeld\tAuctions\tQueued\n=========================\n".to_string()
+                                  + &self.ah.market_totals(admin)?
+                                  .iter()
+                                  .map(|t| format!("{:?}\t{}\t{}\t{}\t{}\n",
+                                                   t.server_type, t.stock, t.held, t.auctions, t.queued))
+                                  .collect::<String>()
+                                 ))
+            },
+            "lockouts" => {
+                Ok(Command::Admin("Account/Address\tFailures\tState\n=========================\n".to_string()
+                                  + &self.ah.lockouts(admin)?
+                                  .iter()
+                                  .map(|l| format!("{}\t{}\t{}\n",
+                                                   l.key,
+                                                   l.failures,
+                                                   if l.locked {
+                                                       format!("locked {}s", l.retry_in.as_secs())
+                                                   } else {
+                                                       format!("retry in {}s", l.retry_in.as_secs())
+                                                   }))
+                                  .collect::<String>()
+                                 ))
+            },
+            "unlock" => {
+                if args.is_empty() { Err("Usage: unlock <email|address>")? }
+                self.ah.unlock(admin, args[0])?;
+                Ok(Command::Admin(format!("{} unlocked", args[0])))
+            },
+            "suspend" | "unsuspend" => {
+                if args.is_empty() { Err(format!("Usage: {} <email>", command))? }
+                self.ah.suspend(admin, args[0], command == "suspend")?;
+                Ok(Command::Admin(format!("{} {}ed", args[0], command)))
+            },
+            "delete" => {
+                if args.is_empty() { Err("Usage: delete <email>")? }
+                let released = self.ah.delete_client(admin, args[0])?;
+                Ok(Command::Admin(format!("{} deleted, {} droplets released", args[0], released)))
+            },
+            "force-drop" => {
+                if args.is_empty() { Err("Usage: force-drop <id>")? }
+                let droplet = self.ah.force_drop(admin, droplet_id(args[0])?)?;
+                Ok(Command::Admin(format!("Dropped {} ({:?}) owned by {}",
+                                          droplet.id(), droplet.server_type(), droplet.owner())))
+            },
+            "cancel-auction" => {
+                if args.is_empty() { Err("Usage: cancel-auction <Fast|Slow>")? }
+                let sv_tp = match ServerType::parse(args[0]) {
+                    None => Err("Invalid server type!")?,
+                    Some(sv_tp) => sv_tp,
+                };
+                self.ah.cancel_auction(admin, sv_tp)?;
+                Ok(Command::Admin(format!("{:?} auction cancelled", sv_tp)))
+            },
+            s => Err(format!("Command not found: {}", s))?,
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.sessions.sessions.write().unwrap().remove(&self.id);
+    }
+}
+
+// Framed replies are for programs: a status line, the reply with lines starting
+// with a dot escaped by another dot, and a lone dot. Anything outside a frame,
+// like a notice, is a single plain line.
+fn frame(result :&str, response :&str) -> String {
+    let status = if result == "ok" { "ok" } else { "error" };
+    let mut out = String::from(status);
+    for line in response.trim_end_matches('\n').lines() {
+        out.push('\n');
+        if line.starts_with('.') {
+            out.push('.');
+        }
+        out.push_str(line);
+    }
+    out + "\n."
+}
+
+// A leading `-o <org>` acts for that organization instead of the user.
+fn org_arg<'a, 'b>(args :&'b [&'a str]) -> (Option<&'a str>, &'b [&'a str]) {
+    match args {
+        ["-o", org, rest @ ..] => (Some(*org), rest),
+        _ => (None, args),
+    }
+}
+
+fn droplet_id(s :&str) -> Result<DropletId, CommandError> {
+    DropletId::parse(s).ok_or_else(|| CommandError("Invalid id: ".to_owned() + s))
+}
+
+fn uptime(d :Duration) -> String {
+    format!("{}h{:02}m{:02}s", d.num_hours(), d.num_minutes() % 60, d.num_seconds() % 60)
 }
@@ -1,30 +1,43 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::thread;
+use crate::clock::Clock;
+
+use chrono::{DateTime, Local};
+
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::sync::Arc;
 
+// Runs once, `delay` seconds after it was created on `clock`, unless cancelled.
 #[derive(Debug, Clone)]
-pub struct Task(Arc<AtomicUsize>);
+pub struct Task {
+    clock :Arc<dyn Clock>,
+    due :DateTime<Local>,
+    cancelled :Arc<AtomicBool>,
+}
 
 impl Task {
-    pub fn new<T> (f :T, delay :usize) -> Self
+    pub fn new<T> (clock :&Arc<dyn Clock>, f :T, delay :usize) -> Self
         where
-        T: FnOnce() -> (),
+        T: FnOnce(),
         T: std::marker::Send + 'static
         {
-            let d = Arc::new(AtomicUsize::new(delay));
-            let task = Task(Arc::clone(&d));
-            thread::spawn(move || {
-                while d.load(Ordering::Relaxed) > 0 {
-                    thread::sleep(Duration::from_secs(1));
-                    d.fetch_sub(1, Ordering::SeqCst);
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let flag = Arc::clone(&cancelled);
+            let due = clock.now() + chrono::Duration::seconds(delay as i64);
+            clock.schedule(Duration::from_secs(delay as u64), Box::new(move || {
+                if !flag.load(Ordering::SeqCst) {
+                    f();
                 }
-                f();
-            });
-            task
+            }));
+            Task { clock: Arc::clone(clock), due, cancelled }
         }
 
+    // Whole seconds left, rounded up.
     pub fn delay(&self) -> usize {
-        self.0.load(Ordering::Relaxed)
+        let left = self.due - self.clock.now();
+        ((left.num_milliseconds() + 999) / 1000).max(0) as usize
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
     }
 }
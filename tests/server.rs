@@ -0,0 +1,214 @@
+use sd_client::{Client, Error, Event, Role, ServerType};
+use sd_rust::auction_house::server_type::ServerType as Stock;
+use sd_rust::config::ServerMode;
+use sd_rust::{Config, Server, ServerHandle};
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+// A free port, so tests can run side by side.
+fn config(stock :u32) -> Config {
+    Config {
+        listen: "127.0.0.1:0".into(),
+        stock: vec![(Stock::Slow, stock), (Stock::Fast, stock)],
+        admins: vec![("root@test".into(), "root".into())],
+        ..Config::default()
+    }
+}
+
+fn start(stock :u32) -> ServerHandle {
+    Server::builder().config(config(stock)).start().unwrap()
+}
+
+#[test]
+fn buy_and_drop() {
+    let server = start(1);
+    let mut client = Client::connect(server.local_addr()).unwrap();
+    client.register("a@test", "a").unwrap();
+    let id = client.buy(ServerType::Fast).unwrap();
+    assert!(matches!(client.buy(ServerType::Fast), Err(Error::OutOfStock(_))));
+    assert_eq!(client.my_droplets().unwrap().len(), 1);
+    client.drop(&id).unwrap();
+    assert!(client.my_droplets().unwrap().is_empty());
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn org_droplets_outlive_members() {
+    let server = start(1);
+    let mut owner = Client::connect(server.local_addr()).unwrap();
+    let mut member = Client::connect(server.local_addr()).unwrap();
+    owner.register("a@test", "a").unwrap();
+    member.register("b@test", "b").unwrap();
+    owner.create_org("team").unwrap();
+    assert!(matches!(member.buy_for("team", ServerType::Fast), Err(Error::PermissionDenied(_))));
+    owner.invite("team", "b@test", Role::Purchaser).unwrap();
+    assert_eq!(member.join_org("team").unwrap(), Role::Purchaser);
+    let id = member.buy_for("team", ServerType::Fast).unwrap();
+    member.leave_org("team").unwrap();
+    assert!(member.my_droplets().unwrap().is_empty());
+    let held = owner.my_droplets().unwrap();
+    assert_eq!((&held[0].id, held[0].owner.as_str()), (&id, "team"));
+    owner.drop(&id).unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn servers_are_isolated() {
+    let servers = (1..=4).map(start).collect::<Vec<_>>();
+    let addrs = servers.iter().map(|s| s.local_addr()).collect::<Vec<_>>();
+    assert!(addrs.iter().all(|a| a.port() != 0));
+    let clients = addrs.into_iter()
+        .enumerate()
+        .map(|(n, addr)| thread::spawn(move || {
+            let mut client = Client::connect(addr).unwrap();
+            // Same account on every server; each has its own accounts and stock.
+            client.register("same@test", "pw").unwrap();
+            let stock = client.stock().unwrap();
+            assert!(stock.iter().all(|o| o.available == n as u32 + 1));
+            for _ in 0..=n {
+                client.buy(ServerType::Slow).unwrap();
+            }
+            assert!(matches!(client.buy(ServerType::Slow), Err(Error::OutOfStock(_))));
+        }))
+        .collect::<Vec<_>>();
+    for client in clients {
+        client.join().unwrap();
+    }
+    for server in servers {
+        server.shutdown().unwrap();
+    }
+}
+
+#[test]
+fn busy_when_no_worker_is_free() {
+    let server = Server::builder().config(Config { workers: 1, ..config(1) }).start().unwrap();
+    let mut first = Client::connect(server.local_addr()).unwrap();
+    assert!(matches!(Client::connect(server.local_addr()), Err(Error::Busy)));
+    first.stock().unwrap();
+    drop(first);
+    // The worker is free again once the server sees the first connection close.
+    let mut second = (0..50)
+        .find_map(|_| Client::connect(server.local_addr()).ok().or_else(|| { thread::sleep(Duration::from_millis(20)); None }))
+        .unwrap();
+    second.stock().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn shutdown_closes_the_listener() {
+    let server = start(1);
+    let addr = server.local_addr();
+    let mut client = Client::connect(addr).unwrap();
+    client.login("root@test", "root").unwrap();
+    server.shutdown().unwrap();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn dropping_the_handle_stops_the_server() {
+    let addr = start(1).local_addr();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn auctions_settle_on_shutdown_without_a_snapshot() {
+    let audit = std::env::temp_dir().join(format!("sd-rust-settle-{}.audit.log", std::process::id()));
+    let server = Server::builder()
+        .config(Config { audit_log: audit.clone(), ..config(1) })
+        .start()
+        .unwrap();
+    let mut client = Client::connect(server.local_addr()).unwrap();
+    client.register("a@test", "a").unwrap();
+    client.bid(ServerType::Fast, 10).unwrap();
+    server.shutdown().unwrap();
+    let log = std::fs::read_to_string(&audit).unwrap();
+    std::fs::remove_file(&audit).unwrap();
+    assert!(log.lines().any(|l| l.contains("\tauction-settle\tFast 10\tdroplet ")));
+}
+
+// Skips other events until one matching `f` arrives, or a few seconds pass.
+fn wait_for<F :Fn(&Event) -> bool>(events :&Receiver<Event>, f :F) -> bool {
+    while let Ok(event) = events.recv_timeout(Duration::from_secs(5)) {
+        if f(&event) { return true }
+    }
+    false
+}
+
+#[test]
+fn event_loop_mode() {
+    let server = Server::builder()
+        .config(Config { mode: ServerMode::Events, event_loops: 2, idle_timeout: 2, ..config(1) })
+        .start()
+        .unwrap();
+    let addr = server.local_addr();
+
+    // Notices are written to a connection from whichever thread sends them.
+    let mut a = Client::connect(addr).unwrap();
+    let mut b = Client::connect(addr).unwrap();
+    let a_events = a.take_events().unwrap();
+    a.register("a@test", "a").unwrap();
+    b.register("b@test", "b").unwrap();
+    a.bid(ServerType::Fast, 10).unwrap();
+    b.bid(ServerType::Fast, 20).unwrap();
+    assert!(wait_for(&a_events, |e| *e == Event::Outbid { server_type: ServerType::Fast, top: 20 }));
+    assert_eq!(b.stock().unwrap().len(), 2);
+
+    // A connection left alone is told and closed by the idle sweep.
+    let mut idle = Client::connect(addr).unwrap();
+    let idle_events = idle.take_events().unwrap();
+    assert!(wait_for(&idle_events, |e| *e == Event::IdleTimeout));
+    assert!(wait_for(&idle_events, |e| *e == Event::Disconnected));
+
+    // Draining tells the sessions still open before closing them.
+    let mut last = Client::connect(addr).unwrap();
+    let last_events = last.take_events().unwrap();
+    last.login("root@test", "root").unwrap();
+    server.shutdown().unwrap();
+    assert!(wait_for(&last_events, |e| *e == Event::ShuttingDown));
+    assert!(TcpStream::connect(addr).is_err());
+
+    // Without an idle sweep, closed connections whose output is never read are
+    // still let go of, so shutting down does not wait on them.
+    let server = Server::builder()
+        .config(Config {
+            mode: ServerMode::Events,
+            event_loops: 6,
+            idle_timeout: 0,
+            rates: HashMap::new(),
+            ..config(2000)
+        })
+        .start()
+        .unwrap();
+    let addr = server.local_addr();
+    let mut owner = Client::connect(addr).unwrap();
+    owner.register("a@test", "a").unwrap();
+    for _ in 0..1700 {
+        owner.buy(ServerType::Slow).unwrap();
+    }
+    // A listing is about 100KB. How much the socket buffers take before output is
+    // left pending varies, so each connection asks for more than the last, and
+    // some are left with less than the limit pending.
+    let mut stalled = (0..6).map(|_| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"login a@test a\n").unwrap();
+        stream
+    }).collect::<Vec<_>>();
+    // Commands are only told apart when they arrive in separate reads.
+    thread::sleep(Duration::from_millis(100));
+    for i in 0..48 {
+        for (n, stream) in stalled.iter_mut().enumerate() {
+            thread::sleep(Duration::from_millis(5));
+            // One left with too much pending has been dropped already.
+            if i < 8 + n * 8 { let _ = stream.write_all(b"ls -m\n"); }
+        }
+    }
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || done.send(server.shutdown().is_ok()).unwrap());
+    assert_eq!(finished.recv_timeout(Duration::from_secs(30)), Ok(true));
+    drop(stalled);
+}